@@ -1,7 +1,7 @@
 #![allow(dead_code)]
 #![feature(member_constraints)]
 
-use lifetime_derive::lifetime;
+use lifetime_derive::{lifetime, lifetimes};
 
 fn main() {}
 
@@ -19,6 +19,462 @@ impl Parser {
     fn parse(&self) -> Result<(), &str> {
         Err(&self.context.0[1..])
     }
+
+    // elided: "self(0) -> Output!(*)"
+    fn peek(&self) -> &Context {
+        self.context
+    }
+}
+
+#[lifetime()]
+impl<'ctx> Parser {
+    #[lifetime("self.context.0 -> 'ctx -> (0)")] // "self.context[Context,0].0(0) -> 'ctx -> Output!(0)"
+    fn rest(&self) -> &str {
+        let rest: &'ctx str = &self.context.0[1..];
+        rest
+    }
+
+    #[lifetime("x -> 'm -> (0)")] // "x(0) -> pick/'m -> Output!(0)"
+    fn pick<'m>(&self, x: &'m str) -> &str {
+        let picked: &'m str = x;
+        picked
+    }
+}
+
+#[lifetime("name -> 'static")]
+fn spawn_named(name: &str) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || println!("{}", name))
+}
+
+#[lifetime("T, name -> 'static")]
+fn spawn_debug<T: std::fmt::Debug + Send>(t: T, name: &str) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || println!("{}: {:?}", name, t))
+}
+
+#[lifetime("first = second")] // "first(0) <-> second(0)"
+struct Pair {
+    first: &str,
+    second: &str,
+}
+
+#[lifetime()]
+impl Pair {
+    fn swap(&mut self) {
+        std::mem::swap(&mut self.first, &mut self.second)
+    }
+}
+
+#[lifetime()]
+struct Source<'a> {
+    #[lifetime(skip)]
+    raw: &'a str,
+    #[lifetime(name = "src")]
+    text: &str,
+    #[lifetime(share = "src")]
+    rest: &str,
+    #[lifetime(static)]
+    origin: &str,
+}
+
+#[lifetime()]
+impl<'a> Source<'a> {
+    fn advance(&mut self) {
+        self.text = self.rest;
+    }
+
+    // elided: "self(0) -> Output!(*)"
+    fn origin(&self) -> &str {
+        self.origin
+    }
+}
+
+#[lifetime("source.text -> (0)")] // "source[Source,0].text(0) -> Output!(0)", 'a stays
+fn source_text<'a>(source: &Source<'a>) -> &str {
+    source.text
+}
+
+#[lifetime("Borrowed -> 'static")] // "Borrowed(0) -> 'static"
+enum Label {
+    Borrowed(&str),
+    Owned(String),
+}
+
+#[lifetime("label -> (0)")] // "label(0) -> Output!(0)", "label[Label,0].Borrowed(0) -> 'static" is carried
+fn label_name(label: &Label) -> &str {
+    match label {
+        Label::Borrowed(name) => name,
+        Label::Owned(name) => name,
+    }
+}
+
+#[lifetime("Ident.context -> Ident.text", "Double.0 = Double.1")] // "Ident(1) -> Ident(0)", "Double(0) <-> Double(1)"
+enum Token {
+    Ident { text: &str, context: &Context },
+    Double(&str, &str),
+}
+
+#[lifetime("token.Ident.text -> (0)")] // "token[Token,0].Ident(0) -> Output!(0)"
+fn token_text(token: Token) -> Option<&str> {
+    match token {
+        Token::Ident { text, .. } => Some(text),
+        Token::Double(..) => None,
+    }
+}
+
+#[lifetime()]
+union Slot {
+    context: &Context,
+    raw: usize,
+}
+
+#[lifetime("slot.context.0 -> (0)")] // "slot[Slot,0].context[Context,0].0(0) -> Output!(0)"
+fn slot_text(slot: Slot) -> &str {
+    unsafe { slot.context.0 }
+}
+
+#[lifetime]
+type Span = (&str, usize);
+
+#[lifetime()]
+struct Spanned {
+    span: &Span,
+}
+
+#[lifetime("spanned.span.type.0 -> (0)")] // "spanned[Spanned,0].span[Span,0].type.0(0) -> Output!(0)"
+fn spanned_text(spanned: Spanned) -> &str {
+    spanned.span.0
+}
+
+#[lifetime()]
+struct Config {
+    name: &str,
+    keywords: &[&str],
+}
+
+#[lifetime]
+static KEYWORDS: &[&str] = &["fn", "struct"];
+
+#[lifetime]
+const DEFAULT: Config = Config {
+    name: "default",
+    keywords: KEYWORDS,
+};
+
+// the lifetimes of the fn types are higher-ranked, not 'static
+#[lifetime]
+const TRIM: fn(&str) -> &str = str::trim;
+
+#[lifetime]
+static TRIM_START: &(dyn Fn(&str) -> &str + Sync) = &str::trim_start;
+
+fn trim_both() -> usize {
+    let text = String::from("  both  ");
+    TRIM(&text).len() + TRIM_START(&text).len()
+}
+
+#[lifetime("config.name -> (0)")] // "config[Config,0].name(0) -> Output!(0)"
+fn config_name(config: &Config) -> &str {
+    config.name
+}
+
+#[lifetime()]
+#[repr(C)]
+struct Buf {
+    data: &u8,
+}
+
+#[lifetime("p.data -> (0)")] // "p[Buf,0].data(0) -> Output!(0)"
+unsafe extern "C" fn view(p: &Buf) -> &u8 {
+    p.data
+}
+
+#[lifetime("p.data -> (0)")] // "p[Buf,0].data(0) -> Output!(0)"
+const fn data(p: &Buf) -> &u8 {
+    p.data
+}
+
+#[lifetime()]
+extern "C" {
+    #[lifetime("b.data -> (0)")] // "b[Buf,0].data(0) -> Output!(0)"
+    fn get(b: &Buf) -> &u8;
+
+    // elided: "data(0) -> Output!(*)"
+    fn first(data: &u8) -> &u8;
+}
+
+#[lifetime()]
+struct Store {
+    values: &[(&str, u32)],
+}
+
+#[lifetime()]
+impl Store {
+    #[lifetime("self.values.* -> (0)")] // "self.values(0), self.values(1) -> Output!(0)"
+    async fn load(&self, key: &str) -> Option<&u32> {
+        self.values.iter().find(|(k, _)| *k == key).map(|(_, v)| v)
+    }
+
+    // elided: "self(0) -> Output!(*)"
+    async fn first(&self) -> Option<&u32> {
+        self.values.first().map(|(_, v)| v)
+    }
+}
+
+#[lifetime("store.values.* -> (0)")] // "store[Store,0].values(0), store[Store,0].values(1) -> Output!(0)"
+async fn load_from(store: &Store, key: &str) -> Option<&u32> {
+    store.load(key).await
+}
+
+#[lifetime()] // no capture bound `+`, the future captures `store` by itself
+async fn count_of(store: &Store) -> impl std::fmt::Display {
+    store.values.len()
+}
+
+#[lifetime("key = Output!(0)")] // both ways, the output is the key
+async fn key_of(store: &Store, key: &str) -> &str {
+    match store.values.iter().any(|(k, _)| *k == key) {
+        true => key,
+        false => "",
+    }
+}
+
+#[lifetime("text = 'a -> Output!(0)")] // through a user lifetime
+async fn head<'a>(text: &str) -> &str {
+    text.split_whitespace().next().unwrap_or("")
+}
+
+#[lifetime("'static -> Output!(0)")] // borrows from none of the inputs
+async fn label(store: &Store) -> &str {
+    match store.values.is_empty() {
+        true => "empty",
+        false => "store",
+    }
+}
+
+#[lifetime()]
+struct Words {
+    text: &str,
+}
+
+#[lifetime()]
+impl Words {
+    // elided: "self(0) -> Output!(*)", the capture bound `Output!(+)` included
+    fn iter(&self) -> impl Iterator<Item = &str> {
+        self.text.split(' ')
+    }
+}
+
+#[lifetime("words.text -> Output!(+), (0)")] // "words[Words,0].text(0) -> Output!.+(0), Output!(0)"
+fn words_of(words: Words) -> impl Iterator<Item = &str> {
+    words.text.split(' ')
+}
+
+#[lifetime()]
+struct Value(&str);
+
+trait Lookup {
+    type Value;
+
+    fn lookup(&self) -> Option<&Self::Value>;
+}
+
+// `M::Value` is the associated type, not the struct `Value`
+#[lifetime("map -> (0)")] // "map(0) -> Output!(0)"
+fn lookup_in<M: Lookup>(map: &M) -> Option<&M::Value> {
+    map.lookup()
+}
+
+trait Cursor {
+    type Item<'a>
+    where
+        Self: 'a;
+
+    fn item(&self) -> Self::Item<'_>;
+}
+
+#[lifetime("cursor -> Output![Cursor].Item")] // "cursor(0) -> Output![Cursor,0].Item(0)"
+fn cursor_item<C: Cursor>(cursor: &C) -> <C as Cursor>::Item<'_> {
+    cursor.item()
+}
+
+#[lifetime()]
+struct Buffer<T, const N: usize> {
+    items: [T; N],
+    name: &str,
+}
+
+#[lifetime()]
+struct Matrix<const N: usize, T> {
+    rows: [[T; N]; N],
+    label: &str,
+}
+
+#[lifetime("buffer.name -> (0)")] // "buffer[Buffer,0].name(0) -> Output!(0)"
+fn buffer_name(buffer: Buffer<&u8, 16>) -> &str {
+    buffer.name
+}
+
+#[lifetime("matrix.label -> (0)")] // "matrix[Matrix,0].label(0) -> Output!(0)"
+fn matrix_label(matrix: &Matrix<{ 2 + 1 }, &f32>) -> &str {
+    matrix.label
+}
+
+macro_rules! str_ref {
+    ($lt:lifetime) => { &$lt str };
+}
+
+macro_rules! field_struct {
+    ($name:ident, $t:ty) => {
+        #[lifetime()]
+        struct $name {
+            value: $t,
+            note: &str,
+        }
+    };
+}
+
+field_struct!(Note, &str); // value is a `$t:ty` group: "value(0)", "note(0)"
+
+macro_rules! list_types {
+    ($list:ident, $entry:ident, $t:ty) => {
+        // `$entry` is declared after `$list` uses it
+        lifetimes! {
+            #[lifetime()]
+            struct $list {
+                head: $entry,
+                rest: &[$entry],
+            }
+
+            #[lifetime()]
+            struct $entry(&str, $t);
+
+            #[lifetime()]
+            impl $list {
+                #[lifetime("self.head.0 -> (0)")] // "self[List,0].head[Entry,0].0(0) -> Output!(0)"
+                fn head_text(&self) -> &str {
+                    self.head.0
+                }
+            }
+        }
+    };
+}
+
+list_types!(List, Entry, &u8);
+
+#[lifetime()] // elided: "text(0) -> Output!(*)"
+fn split_pair(text: &str) -> (&str, &str) {
+    // local, the `Context` outside keeps its single lifetime
+    #[lifetime()]
+    struct Context(&str, &str);
+
+    #[lifetime()]
+    impl Context {
+        #[lifetime("self.1 -> (0)")] // "self[Context,0].1(0) -> Output!(0)"
+        fn second(&self) -> &str {
+            self.1
+        }
+    }
+
+    #[lifetime("context.0 -> (0)", "context.1 -> (1)")] // "context[Context,0].0(0) -> Output!(0)", ..
+    fn parts(context: Context) -> (&str, &str) {
+        (context.0, context.second())
+    }
+
+    let (first, second) = text.split_at(text.len() / 2);
+    parts(Context(first, second))
+}
+
+#[lifetime()] // elided: "text(0) -> Output!(*)"
+fn split_words(text: &str) -> Vec<&str> {
+    if text.is_empty() {
+        // local to the `if`, the `Context` outside keeps its single lifetime
+        #[lifetime()]
+        struct Context(&str, &str);
+
+        #[lifetime("context.1 -> (0)")] // "context[Context,0].1(0) -> Output!(0)"
+        fn second(context: Context) -> &str {
+            context.1
+        }
+
+        return vec![second(Context(text, text))];
+    }
+
+    let words = |text| {
+        // local to the closure
+        #[lifetime()]
+        struct Words(&str);
+
+        #[lifetime("words.0 -> Output!.0")] // "words[Words,0].0(0) -> Output!.0(0)"
+        fn split(words: Words) -> Vec<&str> {
+            words.0.split(' ').collect()
+        }
+
+        split(Words(text))
+    };
+    words(text)
+}
+
+#[lifetime()]
+struct Ctx(&str);
+
+// not annotated, its local `Ctx` is registered for its body all the same
+fn helper() {
+    #[lifetime()]
+    struct Ctx(&str, &str);
+
+    #[lifetime("c.1 -> (0)")] // "c[Ctx,0].1(0) -> Output!(0)", the local `Ctx`
+    fn second(c: &Ctx) -> &str {
+        c.1
+    }
+}
+
+#[lifetime("c.0 -> (0)")] // "c[Ctx,0].0(0) -> Output!(0)", the crate-level `Ctx`
+fn read(c: &Ctx) -> &str {
+    c.0
+}
+
+#[lifetime()]
+struct Titled {
+    title: str_ref!('_),    // declared by hand: "title(0)"
+    tag: str_ref!('static), // opaque
+}
+
+#[lifetime("titled.title -> (0)")] // "titled[Titled,0].title(0) -> Output!(0)"
+fn title(titled: &Titled) -> str_ref!('_) {
+    titled.title
+}
+
+#[lifetime()]
+fn fail(message: &str) -> ! {
+    panic!("{}", message)
+}
+
+#[lifetime()]
+struct Lexer {
+    source: &str,
+    pos: usize,
+}
+
+impl<'src> Lexer<'src> {
+    #[lifetime("Self = Lexer<'src>", "self.source -> (0)")] // "self[Lexer,0].source(0) -> Output!(0)", 'src
+    fn rest(&self) -> &str {
+        &self.source[self.pos..]
+    }
+
+    #[lifetime("Self = Lexer<'src>")] // elided: "self(0) -> Output!(*)"
+    fn advance(&mut self) -> &mut usize {
+        &mut self.pos
+    }
+
+    #[lifetime("self.source -> (0)")] // "self[Lexer,0].source(0) -> Output!(0)", 'src
+    fn into_source(self: Lexer<'src>) -> &str {
+        self.source
+    }
+}
+
+#[lifetime()] // elided: "s(0) -> Output!(*)"
+fn first_word(s: &str) -> &str {
+    s.split(' ').next().unwrap_or(s)
 }
 
 #[lifetime("context.0(0) -> (0)")] // "context[Context,0].0(0) -> Output!(0)"
@@ -35,6 +491,15 @@ fn demo0<T, U>(x: &T, y: &T) -> &T {
     }
 }
 
+#[lifetime("group src = x, y", "x = y -> (0)")] // "x(0) <-> y(0)" named 'src, "'src -> Output!(0)"
+fn demo0_src<T>(x: &T, y: &T) -> &T {
+    if true {
+        x
+    } else {
+        y
+    }
+}
+
 #[lifetime("x(0), y(0) -> (0, 1)")] // "x(0), y(0) -> Output!(0, 1)"
 fn demo1<T, U>(x: &T, y: &T) -> (&T, &T) {
     if true {
@@ -198,6 +663,35 @@ impl<G> Deom5<G> {
             _ => unreachable!(),
         }
     }
+
+    #[lifetime("H -> 'static")]
+    fn demo5_h<H: std::fmt::Debug + Send>(&self, h: H) -> std::thread::JoinHandle<()> {
+        std::thread::spawn(move || println!("{:?}", h))
+    }
+
+    #[lifetime(group input = x, y, z; input -> self.*; self.* -> Output!(*))] // "x(0), y(0), z(0) -> self.x(0), self.y(0), self.z(0)", ...
+    fn demo5_4(&mut self, x: &G, y: &G, z: &G) -> (&G, &G) {
+        self.x = x;
+        self.y = y;
+        self.z = z;
+
+        (self.x, self.z)
+    }
+
+    #[lifetime(
+        "x, y, z -> self.x, self.y, self.z -> (0)",
+        "x, y, z -> (0)",
+        "x -> self.x"
+    )] // "x(0) -> self.x(0) -> Output!(0)", ...
+    fn demo5_3(&self, x: &G, y: &G, z: &G) -> &G {
+        let demo5 = Self { x: x, y: z, z: y };
+
+        match 0 {
+            0 => x,
+            1 => demo5.y,
+            _ => self.z,
+        }
+    }
 }
 
 #[lifetime()]
@@ -229,6 +723,33 @@ impl<T, U> Demo6C<T, U> {
             Demo6B::Multiple(multiple) => multiple.first().map(|a| *a),
         }
     }
+
+    #[lifetime("self -> Output!(0)", "self.b.* -> Output!.*")] // "self(0) -> Output!(0)", "self.b[Demo6B,0].Single(0), ... -> Output!(0), ..."
+    fn b(&self) -> &Demo6B<T, U> {
+        &self.b
+    }
+}
+
+#[lifetime()]
+impl<T, U> Demo6A<T, U> {
+    #[lifetime("self.0 -> Output!.Ok", "self.1 -> Output!.Err.0")] // "self[Demo6A,0].0(0) -> Output!.Ok(0)", "self[Demo6A,0].1(0) -> Output!.Err.0(0)"
+    fn split(&self, ok: bool) -> Result<&T, (&U, usize)> {
+        if ok {
+            Ok(self.0)
+        } else {
+            Err((self.1, 1))
+        }
+    }
+}
+
+#[lifetime("x -> Output!.1", "y -> Output!.0")] // "x(0) -> Output!.1(0)", "y(0) -> Output!.0(0)"
+fn swap_refs<T>(x: &T, y: &T) -> (&T, &T) {
+    (y, x)
+}
+
+#[lifetime("items -> Output!.Some.0")] // "items(0) -> Output!.Some.0(0)"
+fn find_first<T: PartialEq>(items: &[T], item: &T) -> Option<(&T, usize)> {
+    items.iter().position(|x| x == item).map(|i| (&items[i], i))
 }
 
 #[lifetime(
@@ -240,6 +761,23 @@ fn f(x: &i32, mut y: &i32) -> &i32 {
     y
 }
 
+#[lifetime("x(1..3) -> (*)")] // "x(1), x(2) -> Output!(0), Output!(1)"
+fn swap_pair(x: &(&i32, &i32)) -> (&i32, &i32) {
+    (x.1, x.0)
+}
+
+#[lifetime("x(1) = y(1)")] // "x(1) <-> y(1)"
+fn swap(x: &mut &i32, y: &mut &i32) {
+    std::mem::swap(x, y)
+}
+
+#[lifetime("x(1) <-> y(1) -> (0)")] // "x(1) = y(1) -> Output!(0)"
+fn swap_first(x: &mut &i32, y: &mut &i32) -> &i32 {
+    std::mem::swap(x, y);
+
+    *x
+}
+
 #[lifetime()]
 trait Demo7T<T> {
     fn new(x: &T) -> Self
@@ -299,8 +837,16 @@ where
     }
 }
 
+#[lifetime()]
+impl<T: Ord> Deom7B<T> {
+    #[lifetime("x -> U.new/x")] // "x(0) -> make/generics/U[Demo7T,0].new/x(0)"
+    fn make<U: Demo7T<T>>(x: &T) -> U {
+        U::new(x)
+    }
+}
+
 #[lifetime(
-    // "x -> Output![Demo7T,0].new/x, Output![Demo7T,1].new/x"
+    "x -> U1.new/x, U2.new/x" // "x(0) -> generics/U1[Demo7T,0].new/x(0), generics/U2[Demo7T,0].new/x(0)"
 )]
 fn demo7c<T, U1: Demo7T<T>, U2: Demo7T<T>>(x: &T, y: &T) -> (U1, U2)
 where
@@ -339,8 +885,7 @@ where
 }
 */
 
-/*
-#[lifetime("(0, 1) -> (2)")]
+#[lifetime("(0, 1) -> (2)")] // "Output!.0(0), Output!.1(0) -> Output!.Output!(0)", all in `for<..>`
 fn demo8<T>() -> fn(&T, &T) -> &T {
     |a, b| {
         if true {
@@ -351,8 +896,7 @@ fn demo8<T>() -> fn(&T, &T) -> &T {
     }
 }
 
-
-#[lifetime("(1, 2) -> (3)")]
+#[lifetime("(1, 2) -> (3)")] // "Output!.0(0), Output!.1(0) -> Output!.Output!(0)", all in `for<..>`
 fn demo9<T>() -> &impl Fn(&T, &T) -> &T {
     &|a, b| {
         if true {
@@ -362,7 +906,49 @@ fn demo9<T>() -> &impl Fn(&T, &T) -> &T {
         }
     }
 }
-*/
+
+#[lifetime()]
+struct Wrap<F, G> {
+    f: F,
+    g: G,
+    s: &str,
+}
+
+// the bare fn moves when `Wrap`'s lifetime is pushed in front of it, its `for<..>` is kept
+#[lifetime("w.s -> (0)", "w.0.0 -> w.0.Output!")] // "w[Wrap,0].s(0) -> Output!(0)", "w.0.0(0) -> w.0.Output!(0)"
+fn wrapped(w: Wrap<fn(&str) -> &str, u8>) -> &str {
+    (w.f)(w.s)
+}
+
+#[lifetime()] // no edges in the `for<..>` of `Fn`, its lifetimes are elided
+struct Cb {
+    f: Box<dyn Fn(&str) -> &str>,
+}
+
+#[lifetime()]
+type Visitor = Box<dyn Fn(&Context) -> bool>;
+
+#[lifetime()]
+struct Walker {
+    visitor: Visitor,
+    cb: &Cb,
+}
+
+// elided: "text(0) -> Output!(*)", the `Fn` keeps its own
+#[lifetime()]
+fn trim_with(text: &str) -> Box<dyn Fn(&str) -> &str + '_> {
+    Box::new(move |s| s.trim_start_matches(text))
+}
+
+#[lifetime()] // no capture bound, `dyn Debug` is `dyn Debug + 'static`
+struct Dbg {
+    d: Box<dyn std::fmt::Debug>,
+}
+
+#[lifetime("prefix -> 'p", "Output!.0.0 -> Output!.0.Output!")] // "prefix(0) -> 'p", "Output!.0.0(0) -> Output!.0.Output!(0)", a `dyn` keeps its bound
+fn demo9_box<'p>(prefix: &str) -> Box<dyn FnMut(&str) -> &str + 'p> {
+    Box::new(move |s| s.strip_prefix(prefix).unwrap_or(s))
+}
 
 /*
 struct Deom7_S {}
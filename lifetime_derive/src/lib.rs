@@ -1,68 +1,299 @@
 #![feature(box_patterns)]
+#![feature(proc_macro_span)]
 
 extern crate proc_macro;
 
 mod ref_nodes;
+mod sites;
 
-use alias_trie::Trie;
+use alias_trie::{Trie, UniqueOption};
 use itertools::concat;
 use lazy_static::lazy_static;
 use proc_macro::TokenStream;
 use proc_macro2::{Group, Span, TokenTree};
 use quote::quote;
-use ref_nodes::{get_ref_digrphs, RDigrph, RNode, ROrigin};
+use ref_nodes::{
+    format_digrph_name, get_ref_digrphs, set_macro_lifetimes, Edge, RDigrph, RNode, ROrigin,
+};
 use regex::{Captures, Regex};
+use sites::{get_nested_site, is_in_scope, set_site};
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Mutex;
+use syn::fold::Fold;
 use syn::*;
 
 lazy_static! {
     static ref LIFETIME_COORDS_MAP: Mutex<HashMap<String, Vec<(String, u8)>>> =
         Mutex::new(HashMap::new());
+    static ref LIFETIME_PATHS_MAP: Mutex<HashMap<String, Vec<(String, u8)>>> =
+        Mutex::new(HashMap::new());
+    static ref LIFETIME_EDGES_MAP: Mutex<HashMap<String, Vec<Edge>>> =
+        Mutex::new(HashMap::new());
+    // coords merged into an earlier one or into 'static have no param of their own
+    static ref LIFETIME_PARAMS_MAP: Mutex<HashMap<String, Vec<bool>>> =
+        Mutex::new(HashMap::new());
+    // the type params declared in the item being expanded, `T` of `T::Item`
+    static ref TYPE_PARAMS: Mutex<Vec<String>> = Mutex::new(vec![]);
 }
 
 #[proc_macro_attribute]
 pub fn lifetime(args: TokenStream, input: TokenStream) -> TokenStream {
-    match parse_macro_input!(input as Item) {
-        Item::Struct(struct_) => macro_struct(struct_),
-        Item::Enum(enum_) => macro_enum(enum_),
-        Item::Impl(impl_) => macro_impl(impl_),
-        Item::Fn(fn_) => {
-            let args = parse_macro_input!(args as AttributeArgs);
-            let args: Vec<String> = args
-                .iter()
-                .filter_map(|arg| {
-                    if let NestedMeta::Lit(Lit::Str(arg)) = arg {
-                        Some(arg.value())
-                    } else {
-                        None
-                    }
-                })
-                .collect();
+    set_site(proc_macro::Span::call_site());
+    macro_item(args.into(), parse_macro_input!(input as Item))
+}
+
+// `lifetimes! { #[lifetime()] struct ... impl ... }`, a batch of items for `macro_rules!`.
+// The attribute and the function-like macro can't share the name `lifetime`.
+#[proc_macro]
+pub fn lifetimes(input: TokenStream) -> TokenStream {
+    set_site(proc_macro::Span::call_site());
+    let Items(items) = parse_macro_input!(input as Items);
+    let outputs = macro_items(items);
+
+    quote!(#(#outputs)*).into()
+}
+
+fn macro_items(mut items: Vec<Item>) -> Vec<proc_macro2::TokenStream> {
+    let mut outputs = vec![proc_macro2::TokenStream::new(); items.len()];
+    for i in get_batch_order(&items) {
+        let item = std::mem::replace(&mut items[i], Item::Verbatim(quote!()));
+        outputs[i] = match take_lifetime_args(item) {
+            (Some(args), item) => macro_item(args, item).into(),
+            (None, item) => quote!(#item),
+        };
+    }
+
+    outputs
+}
+
+// The items declared in a fn body are expanded with the fn. Their types are registered only
+// while the body is, over the types of the same name outside, and left verbatim so the fn's
+// own renames don't reach them. Nested blocks, of an `if`, a `loop` or a closure, see the
+// items around them and scope their own ones the same way.
+fn set_local_items(block: &mut Block) {
+    let indices = (0..block.stmts.len())
+        .filter(|&i| matches!(block.stmts[i], Stmt::Item(_)))
+        .collect::<Vec<_>>();
+    if indices.is_empty() {
+        set_nested_local_items(block);
+        return;
+    }
+
+    let items = indices
+        .iter()
+        .map(|&i| {
+            match std::mem::replace(&mut block.stmts[i], Stmt::Item(Item::Verbatim(quote!()))) {
+                Stmt::Item(item) => item,
+                _ => unreachable!(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let coords_map = LIFETIME_COORDS_MAP.lock().unwrap().clone();
+    let paths_map = LIFETIME_PATHS_MAP.lock().unwrap().clone();
+    let edges_map = LIFETIME_EDGES_MAP.lock().unwrap().clone();
+    let params_map = LIFETIME_PARAMS_MAP.lock().unwrap().clone();
+    for item in items.iter() {
+        if let Item::Struct(ItemStruct { ident, .. })
+        | Item::Enum(ItemEnum { ident, .. })
+        | Item::Union(ItemUnion { ident, .. })
+        | Item::Type(ItemType { ident, .. })
+        | Item::Trait(ItemTrait { ident, .. }) = item
+        {
+            let name = get_registry_key(&ident.to_string());
+            LIFETIME_COORDS_MAP.lock().unwrap().remove(&name);
+            LIFETIME_PATHS_MAP.lock().unwrap().remove(&name);
+            LIFETIME_EDGES_MAP.lock().unwrap().remove(&name);
+            LIFETIME_PARAMS_MAP.lock().unwrap().remove(&name);
+        }
+    }
+
+    let outputs = macro_items(items);
+    set_nested_local_items(block);
+
+    *LIFETIME_COORDS_MAP.lock().unwrap() = coords_map;
+    *LIFETIME_PATHS_MAP.lock().unwrap() = paths_map;
+    *LIFETIME_EDGES_MAP.lock().unwrap() = edges_map;
+    *LIFETIME_PARAMS_MAP.lock().unwrap() = params_map;
+
+    for (i, output) in indices.into_iter().zip(outputs) {
+        block.stmts[i] = Stmt::Item(Item::Verbatim(output));
+    }
+}
+
+fn set_nested_local_items(block: &mut Block) {
+    for stmt in block.stmts.iter_mut() {
+        if !matches!(stmt, Stmt::Item(_)) {
+            *stmt = LocalItemsFolder.fold_stmt(stmt.clone());
+        }
+    }
+}
+
+// The outermost blocks of a statement, the items in between are expanded on their own.
+struct LocalItemsFolder;
+
+impl Fold for LocalItemsFolder {
+    fn fold_block(&mut self, mut block: Block) -> Block {
+        set_local_items(&mut block);
+        block
+    }
 
-            macro_fn(args, fn_)
+    fn fold_item(&mut self, item: Item) -> Item {
+        item
+    }
+}
+
+struct Items(Vec<Item>);
+
+impl parse::Parse for Items {
+    fn parse(input: parse::ParseStream) -> Result<Self> {
+        let mut items = vec![];
+        while !input.is_empty() {
+            items.push(input.parse()?);
         }
+
+        Ok(Items(items))
+    }
+}
+
+// The registered types of the batch go first, each after the batch types it uses, so the
+// items after them can resolve their coords. Traits go before the impls and fns.
+fn get_batch_order(items: &[Item]) -> Vec<usize> {
+    let names = items
+        .iter()
+        .map(|item| match item {
+            Item::Struct(ItemStruct { ident, .. })
+            | Item::Enum(ItemEnum { ident, .. })
+            | Item::Union(ItemUnion { ident, .. })
+            | Item::Type(ItemType { ident, .. }) => Some(ident.to_string()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let mut order = vec![];
+    let mut pending = (0..items.len())
+        .filter(|&i| names[i].is_some())
+        .collect::<Vec<_>>();
+    while !pending.is_empty() {
+        let ready = pending
+            .iter()
+            .position(|&i| {
+                let item = &items[i];
+                let idents = get_idents(quote!(#item));
+                pending
+                    .iter()
+                    .all(|&j| j == i || !idents.contains(names[j].as_ref().unwrap()))
+            })
+            // a cycle, take them in order
+            .unwrap_or(0);
+        order.push(pending.remove(ready));
+    }
+
+    let is_trait = |i: &usize| matches!(items[*i], Item::Trait(_));
+    order.extend((0..items.len()).filter(is_trait));
+    order.extend((0..items.len()).filter(|i| names[*i].is_none() && !is_trait(i)));
+
+    order
+}
+
+fn get_idents(tokens: proc_macro2::TokenStream) -> Vec<String> {
+    concat(tokens.into_iter().map(|token| match token {
+        TokenTree::Ident(ident) => vec![ident.to_string()],
+        TokenTree::Group(group) => get_idents(group.stream()),
+        _ => vec![],
+    }))
+}
+
+// The args of the `#[lifetime(..)]` on an item of the batch, the item is left as it is without one.
+fn take_lifetime_args(mut item: Item) -> (Option<proc_macro2::TokenStream>, Item) {
+    let attrs = match &mut item {
+        Item::Struct(ItemStruct { attrs, .. })
+        | Item::Enum(ItemEnum { attrs, .. })
+        | Item::Impl(ItemImpl { attrs, .. })
+        | Item::Fn(ItemFn { attrs, .. })
+        | Item::Trait(ItemTrait { attrs, .. })
+        | Item::Union(ItemUnion { attrs, .. })
+        | Item::Type(ItemType { attrs, .. })
+        | Item::ForeignMod(ItemForeignMod { attrs, .. })
+        | Item::Static(ItemStatic { attrs, .. })
+        | Item::Const(ItemConst { attrs, .. }) => attrs,
+        _ => return (None, item),
+    };
+
+    let args = attrs
+        .iter()
+        .position(|attr| attr.path.is_ident("lifetime"))
+        .map(|i| {
+            let attr = attrs.remove(i);
+            match syn::parse2::<Group>(attr.tokens) {
+                Ok(group) => group.stream(),
+                Err(_) => proc_macro2::TokenStream::new(),
+            }
+        });
+
+    (args, item)
+}
+
+fn macro_item(args: proc_macro2::TokenStream, item: Item) -> TokenStream {
+    // the items of a fn body are expanded inside the fn's own expansion
+    let mut collector = TypeParamsCollector(vec![]);
+    let item = collector.fold_item(item);
+    let outer = std::mem::replace(&mut *TYPE_PARAMS.lock().unwrap(), collector.0);
+
+    let output = match item {
+        Item::Struct(struct_) => macro_struct(get_lifetime_statements(args), struct_),
+        Item::Enum(enum_) => macro_enum(get_lifetime_statements(args), enum_),
+        Item::Impl(impl_) => macro_impl(impl_),
+        Item::Fn(fn_) => macro_fn(get_lifetime_statements(args), fn_),
         Item::Trait(trait_) => macro_trait(trait_),
+        Item::Union(union_) => macro_union(get_lifetime_statements(args), union_),
+        Item::Type(type_) => macro_type(get_lifetime_statements(args), type_),
+        Item::ForeignMod(foreign_mod) => macro_foreign_mod(foreign_mod),
+        Item::Static(static_) => macro_static(static_),
+        Item::Const(const_) => macro_const(const_),
         _ => unreachable!(),
         /*
-        Item::Const(_) => {}
         Item::ExternCrate(_) => {}
-        Item::ForeignMod(_) => {}
         Item::Macro(_) => {}
         Item::Macro2(_) => {}
         Item::Mod(_) => {}
-        Item::Static(_) => {}
         Item::TraitAlias(_) => {}
-        Item::Type(_) => {}
-        Item::Union(_) => {}
         Item::Use(_) => {}
         Item::Verbatim(_) => {}
         Item::__Nonexhaustive => {}
         */
+    };
+
+    *TYPE_PARAMS.lock().unwrap() = outer;
+    output
+}
+
+struct TypeParamsCollector(Vec<String>);
+
+impl Fold for TypeParamsCollector {
+    fn fold_type_param(&mut self, tp: TypeParam) -> TypeParam {
+        self.0.push(tp.ident.to_string());
+        tp
+    }
+
+    // the items of a body collect their own
+    fn fold_block(&mut self, block: Block) -> Block {
+        block
     }
 }
 
-fn macro_struct(mut struct_: ItemStruct) -> TokenStream {
+// `T` of `T::Item` or `Self`, a path after it is an associated item
+fn is_type_param(name: &str) -> bool {
+    name == "Self"
+        || TYPE_PARAMS
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|param| param == name)
+}
+
+fn macro_struct(args: Vec<String>, mut struct_: ItemStruct) -> TokenStream {
     //println!("{:#?}", struct_);
     let symbol_generator = &mut SymbolGenerator::new(String::from("s_"));
 
@@ -70,13 +301,18 @@ fn macro_struct(mut struct_: ItemStruct) -> TokenStream {
     let origins = vec![ROrigin::StructFields(&mut struct_.fields)];
     let mut digrphs = get_ref_digrphs(name.clone(), origins);
 
-    set_lifetime_symbols(Some(&mut struct_.generics), &mut digrphs, symbol_generator);
-    set_lifetime_coords(name, &mut digrphs);
+    let scoped = set_lifetime_symbols(Some(&mut struct_.generics), &mut digrphs, symbol_generator);
+    set_lifetime_coords(name.clone(), &digrphs);
+
+    let (edges, renames) =
+        set_type_lifetime_bounds(name.clone(), &mut struct_.generics, &digrphs, scoped, args);
+    set_lifetime_edges(name, edges);
+    let struct_ = LifetimeRenamer { renames }.fold_item_struct(struct_);
 
     quote!(#struct_).into()
 }
 
-fn macro_enum(mut enum_: ItemEnum) -> TokenStream {
+fn macro_enum(args: Vec<String>, mut enum_: ItemEnum) -> TokenStream {
     //println!("{:#?}", enum_);
     let symbol_generator = &mut SymbolGenerator::new(String::from("e_"));
 
@@ -84,19 +320,136 @@ fn macro_enum(mut enum_: ItemEnum) -> TokenStream {
     let origins = vec![ROrigin::EnumVariants(&mut enum_.variants)];
     let mut digrphs = get_ref_digrphs(name.clone(), origins);
 
-    set_lifetime_symbols(Some(&mut enum_.generics), &mut digrphs, symbol_generator);
-    set_lifetime_coords(name, &mut digrphs);
+    let scoped = set_lifetime_symbols(Some(&mut enum_.generics), &mut digrphs, symbol_generator);
+    set_lifetime_coords(name.clone(), &digrphs);
+
+    let (edges, renames) =
+        set_type_lifetime_bounds(name.clone(), &mut enum_.generics, &digrphs, scoped, args);
+    set_lifetime_edges(name, edges);
+    let enum_ = LifetimeRenamer { renames }.fold_item_enum(enum_);
 
     quote!(#enum_).into()
 }
 
+fn macro_union(args: Vec<String>, mut union_: ItemUnion) -> TokenStream {
+    let symbol_generator = &mut SymbolGenerator::new(String::from("u_"));
+
+    // the fields of a union are named struct fields
+    let name = union_.ident.to_string();
+    let mut fields = Fields::Named(union_.fields.clone());
+    let origins = vec![ROrigin::StructFields(&mut fields)];
+    let mut digrphs = get_ref_digrphs(name.clone(), origins);
+
+    let scoped = set_lifetime_symbols(Some(&mut union_.generics), &mut digrphs, symbol_generator);
+    set_lifetime_coords(name.clone(), &digrphs);
+
+    let (edges, renames) =
+        set_type_lifetime_bounds(name.clone(), &mut union_.generics, &digrphs, scoped, args);
+    set_lifetime_edges(name, edges);
+    if let Fields::Named(fields) = fields {
+        union_.fields = fields;
+    }
+    let union_ = LifetimeRenamer { renames }.fold_item_union(union_);
+
+    quote!(#union_).into()
+}
+
+fn macro_type(args: Vec<String>, mut type_: ItemType) -> TokenStream {
+    let symbol_generator = &mut SymbolGenerator::new(String::from("a_"));
+
+    // the aliased type is addressed as `type`, e.g. `span.type.0`
+    let name = type_.ident.to_string();
+    let origins = vec![ROrigin::ItemTY(&mut type_.ty)];
+    let mut digrphs = get_ref_digrphs(name.clone(), origins);
+
+    let scoped = set_lifetime_symbols(Some(&mut type_.generics), &mut digrphs, symbol_generator);
+    set_lifetime_coords(name.clone(), &digrphs);
+
+    let (edges, renames) =
+        set_type_lifetime_bounds(name.clone(), &mut type_.generics, &digrphs, scoped, args);
+    set_lifetime_edges(name, edges);
+    let type_ = LifetimeRenamer { renames }.fold_item_type(type_);
+
+    quote!(#type_).into()
+}
+
+fn macro_static(mut static_: ItemStatic) -> TokenStream {
+    let renames = set_static_lifetime_symbols(&mut static_.ty);
+    let static_ = LifetimeRenamer { renames }.fold_item_static(static_);
+
+    quote!(#static_).into()
+}
+
+fn macro_const(mut const_: ItemConst) -> TokenStream {
+    let renames = set_static_lifetime_symbols(&mut const_.ty);
+    let const_ = LifetimeRenamer { renames }.fold_item_const(const_);
+
+    quote!(#const_).into()
+}
+
+// Statics and consts have no generics, every generated lifetime is renamed to 'static, but the
+// higher-ranked ones of `fn(&str) -> &str` or `dyn Fn(&str) -> &str`, they stay elided.
+fn set_static_lifetime_symbols(ty: &mut Box<Type>) -> HashMap<String, String> {
+    let symbol_generator = &mut SymbolGenerator::new(String::from("c_"));
+
+    let mut generics = Generics::default();
+    let origins = vec![ROrigin::ItemTY(ty)];
+    let mut digrphs = get_ref_digrphs("".to_string(), origins);
+    let scoped = set_lifetime_symbols(Some(&mut generics), &mut digrphs, symbol_generator);
+
+    let mut renames = generics
+        .lifetimes()
+        .map(|lf_def| lf_def.lifetime.to_string())
+        .filter(|symbol| {
+            !scoped
+                .iter()
+                .any(|(scoped_symbol, _)| scoped_symbol == symbol)
+        })
+        .map(|symbol| (symbol, "'static".to_string()))
+        .collect();
+    set_scoped_lifetimes(&mut generics, scoped, &mut renames);
+
+    renames
+}
+
 fn macro_impl(mut impl_: ItemImpl) -> TokenStream {
     //println!("{:#?}", impl_);
 
-    //let local_lifetime_coords_map = HashMap::new();
     let symbol_generator = &mut SymbolGenerator::new(String::from("i_"));
 
+    for item in impl_.items.iter_mut() {
+        if let ImplItem::Method(iim) = item {
+            set_local_items(&mut iim.block);
+        }
+    }
+
+    // a method's own lifetimes are renamed apart while the bounds are set, `'m` of `pick`
+    // is `'pick_m`, they are renamed back at the end
+    let mut method_lifetimes = vec![];
+    for item in impl_.items.iter_mut() {
+        if let ImplItem::Method(iim) = item {
+            let name = iim.sig.ident.to_string();
+            let renames = iim
+                .sig
+                .generics
+                .lifetimes()
+                .map(|lf_def| {
+                    let lifetime = lf_def.lifetime.to_string();
+                    let unique = format!("'{}_{}", name, &lifetime[1..]);
+                    (lifetime, unique)
+                })
+                .collect::<HashMap<_, _>>();
+            *iim = LifetimeRenamer {
+                renames: renames.clone(),
+            }
+            .fold_impl_item_method(iim.clone());
+            method_lifetimes.push((name, renames));
+        }
+    }
+
     let mut coords = vec![];
+    let mut paths = vec![];
+    let mut carried_edges = vec![];
 
     // impl_.self_ty
     //println!("impl_.self_ty: {:#?}", impl_.self_ty);
@@ -106,6 +459,10 @@ fn macro_impl(mut impl_: ItemImpl) -> TokenStream {
     set_lifetime_symbols(Some(&mut impl_.generics), &mut digrphs, symbol_generator);
     // coords
     coords.extend(concat(digrphs.iter().map(|digrph| digrph.get_coords())));
+    paths.extend(concat(digrphs.iter().map(|digrph| digrph.get_paths())));
+    carried_edges.extend(concat(
+        digrphs.iter().map(|digrph| digrph.get_carried_edges()),
+    ));
 
     // impl_.generics
     let origins = vec![ROrigin::Generics(&mut impl_.generics)];
@@ -114,6 +471,10 @@ fn macro_impl(mut impl_: ItemImpl) -> TokenStream {
     set_lifetime_symbols(Some(&mut impl_.generics), &mut digrphs, symbol_generator);
     // coords
     coords.extend(concat(digrphs.iter().map(|digrph| digrph.get_coords())));
+    paths.extend(concat(digrphs.iter().map(|digrph| digrph.get_paths())));
+    carried_edges.extend(concat(
+        digrphs.iter().map(|digrph| digrph.get_carried_edges()),
+    ));
 
     // impl_.trait_
     let origins = vec![ROrigin::Trait(&mut impl_.trait_)];
@@ -124,6 +485,8 @@ fn macro_impl(mut impl_: ItemImpl) -> TokenStream {
     // `impl_.items` immediately follow `impl_.trait_`, because their symbols is the same.
     // The `symbol_generator` will roll back the symbols of the `impl_.trait_`.
     // impl_.items
+    let mut elided_edges = HashMap::new();
+    let mut scoped = vec![];
     for item in impl_.items.iter_mut() {
         //println!("item: {:#?}", item);
         match item {
@@ -131,15 +494,29 @@ fn macro_impl(mut impl_: ItemImpl) -> TokenStream {
                 let name = iim.sig.ident.to_string();
 
                 // set lifetime symbols
-                let origins = vec![
-                    ROrigin::FnInputs(&mut iim.sig.inputs),
-                    ROrigin::FnOutput(&mut iim.sig.output),
-                ];
-                let mut digrphs = get_ref_digrphs(name, origins);
-                set_lifetime_symbols(Some(&mut impl_.generics), &mut digrphs, symbol_generator);
+                let output = match iim.sig.asyncness {
+                    Some(_) => ROrigin::AsyncFnOutput(&mut iim.sig.output),
+                    None => ROrigin::FnOutput(&mut iim.sig.output),
+                };
+                let origins = vec![ROrigin::FnInputs(&mut iim.sig.inputs), output];
+                let mut digrphs = get_ref_digrphs(name.clone(), origins);
+                scoped.extend(set_lifetime_symbols(
+                    Some(&mut impl_.generics),
+                    &mut digrphs,
+                    symbol_generator,
+                ));
 
                 // coords
                 coords.extend(concat(digrphs.iter().map(|digrph| digrph.get_coords())));
+                paths.extend(concat(digrphs.iter().map(|digrph| digrph.get_paths())));
+                carried_edges.extend(concat(
+                    digrphs.iter().map(|digrph| digrph.get_carried_edges()),
+                ));
+                carried_edges.extend(concat(
+                    digrphs.iter().map(|digrph| digrph.get_capture_edges()),
+                ));
+
+                elided_edges.insert(name, get_elided_edges(&digrphs));
             }
             _ => unreachable!(),
             /*
@@ -152,27 +529,74 @@ fn macro_impl(mut impl_: ItemImpl) -> TokenStream {
         }
     }
 
-    // edges
-    let mut edges = vec![];
+    // methods' generics, after all the items so the trait symbols above stay in step
+    for item in impl_.items.iter_mut() {
+        if let ImplItem::Method(iim) = item {
+            let name = iim.sig.ident.to_string();
+
+            let origins = vec![ROrigin::Generics(&mut iim.sig.generics)];
+            let mut digrphs = get_ref_digrphs(format!("{}/generics", name), origins);
+            // set lifetime symbols
+            set_lifetime_symbols(Some(&mut impl_.generics), &mut digrphs, symbol_generator);
+            // coords
+            coords.extend(concat(digrphs.iter().map(|digrph| digrph.get_coords())));
+            paths.extend(concat(digrphs.iter().map(|digrph| digrph.get_paths())));
+            carried_edges.extend(concat(
+                digrphs.iter().map(|digrph| digrph.get_carried_edges()),
+            ));
+        }
+    }
+
+    // edges, starting with the ones the used types carry and the capture bounds
+    let mut edges = carried_edges;
+    let mut groups = vec![];
     for item in impl_.items.iter_mut() {
         //println!("item: {:#?}", item);
         match item {
             ImplItem::Method(iim) => {
                 let name = iim.sig.ident.to_string();
 
+                let mut statements = vec![];
                 for attr in iim.attrs.iter() {
                     if attr.path.segments[0].ident.to_string() == "lifetime" {
                         let group: Group = syn::parse2(attr.tokens.clone()).unwrap();
-                        for token in group.stream() {
-                            if let TokenTree::Literal(li) = token {
-                                edges.extend(get_edges(
-                                    name.clone(),
-                                    li.to_string().trim_matches('"').to_string(),
-                                ));
-                            }
+                        statements.extend(get_lifetime_statements(group.stream()));
+                    }
+                }
+
+                // without edges of its own a method follows the elision rules
+                if statements.is_empty() {
+                    edges.extend(elided_edges.remove(&name).unwrap_or(vec![]));
+                }
+
+                // the method's own lifetimes by their names apart
+                let (_, lifetimes) = method_lifetimes
+                    .iter()
+                    .find(|(method, _)| *method == name)
+                    .unwrap();
+                for (lifetime, unique) in lifetimes.iter() {
+                    let lifetime_re = Regex::new(&format!(r"{}\b", lifetime)).unwrap();
+                    statements = statements
+                        .iter()
+                        .map(|statement| {
+                            lifetime_re
+                                .replace_all(statement, unique.as_str())
+                                .to_string()
+                        })
+                        .collect();
+                }
+
+                let method_groups = get_groups(name.clone(), &statements);
+                for statement in statements {
+                    let statement_edges = get_edges(name.clone(), statement, &method_groups);
+                    if iim.sig.asyncness.is_some() {
+                        if let Err(error) = check_async_edges(&name, &statement_edges) {
+                            return error.to_compile_error().into();
                         }
                     }
+                    edges.extend(statement_edges);
                 }
+                groups.extend(method_groups);
 
                 // remove instance lifetime macro
                 iim.attrs
@@ -182,65 +606,368 @@ fn macro_impl(mut impl_: ItemImpl) -> TokenStream {
         }
     }
 
-    set_generic_lifetime_bounds(
-        impl_
-            .generics
-            .params
-            .iter_mut()
-            .filter(|gp| match gp {
-                GenericParam::Lifetime(_) => true,
-                _ => false,
-            })
-            .collect::<Vec<_>>(),
-        coords,
-        edges,
-    );
+    // the generated lifetimes live on the impl and come last, after the user lifetimes of the
+    // impl and of its methods, type params on the impl or its methods
+    let mut gps = impl_
+        .generics
+        .params
+        .iter_mut()
+        .map(|gp| (String::new(), gp))
+        .collect::<Vec<_>>();
+    let user = gps
+        .iter()
+        .filter(|(_, gp)| matches!(gp, GenericParam::Lifetime(_)))
+        .count()
+        .checked_sub(coords.len())
+        .expect("every coord has a generated lifetime on the impl");
+    let mut generated = gps.split_off(user);
+    let mut tps = vec![];
+    for item in impl_.items.iter_mut() {
+        if let ImplItem::Method(iim) = item {
+            let name = iim.sig.ident.to_string();
+            for gp in iim.sig.generics.params.iter_mut() {
+                if matches!(gp, GenericParam::Lifetime(_)) {
+                    gps.push((name.clone(), gp));
+                } else if matches!(gp, GenericParam::Type(_)) {
+                    tps.push((name.clone(), gp));
+                }
+            }
+        }
+    }
+    gps.append(&mut generated);
+    gps.append(&mut tps);
+
+    let mut renames = set_generic_lifetime_bounds(gps, coords, paths, edges, groups);
+    set_scoped_lifetimes(&mut impl_.generics, scoped, &mut renames);
+    set_method_lifetime_bounds(&mut impl_, &method_lifetimes, &mut renames);
+    let mut impl_ = LifetimeRenamer { renames }.fold_item_impl(impl_);
+
+    // the method's own lifetimes by their names again
+    for (item, (_, renames)) in impl_
+        .items
+        .iter_mut()
+        .filter(|item| matches!(item, ImplItem::Method(_)))
+        .zip(method_lifetimes)
+    {
+        if let ImplItem::Method(iim) = item {
+            let renames = renames
+                .into_iter()
+                .map(|(lifetime, unique)| (unique, lifetime))
+                .collect();
+            *iim = LifetimeRenamer { renames }.fold_impl_item_method(iim.clone());
+        }
+    }
 
     quote!(#impl_).into()
 }
 
 fn macro_fn(args: Vec<String>, mut fn_: ItemFn) -> TokenStream {
+    set_local_items(&mut fn_.block);
+
+    // a method of a hand-written impl, `Self = Parser<'a>` gives its self type
+    let self_re = Regex::new(r"^\s*Self\s*=(.+)$").unwrap();
+    let (self_tys, args): (Vec<_>, Vec<_>) =
+        args.into_iter().partition(|arg| self_re.is_match(arg));
+    if let Some(self_ty) = self_tys.last() {
+        let self_ty = parse_str::<Type>(&self_re.replace(self_ty, "$1")).unwrap();
+        set_typed_receiver(&mut fn_.sig, self_ty);
+    }
+    let impl_lifetimes = take_impl_lifetimes(&mut fn_.sig);
+
+    let renames = match set_sig_lifetime_bounds(args, &mut fn_.sig) {
+        Ok(renames) => renames,
+        Err(error) => return error.to_compile_error().into(),
+    };
+    let mut fn_ = LifetimeRenamer { renames }.fold_item_fn(fn_);
+
+    if let Some(impl_lifetimes) = impl_lifetimes {
+        set_impl_lifetimes(&mut fn_.sig, impl_lifetimes);
+    }
+
+    quote!(#fn_).into()
+}
+
+// `&mut self` is `self: &mut Parser<'a>`
+fn set_typed_receiver(sig: &mut Signature, self_ty: Type) {
+    for input in sig.inputs.iter_mut() {
+        if let FnArg::Receiver(Receiver {
+            attrs,
+            reference,
+            mutability,
+            ..
+        }) = input
+        {
+            let ty: Type = match reference {
+                Some((_, lifetime)) => parse_quote!(&#lifetime #mutability #self_ty),
+                None => self_ty.clone(),
+            };
+            let mutability = match reference {
+                Some(_) => None,
+                None => *mutability,
+            };
+
+            *input = FnArg::Typed(PatType {
+                attrs: attrs.clone(),
+                pat: parse_quote!(#mutability self),
+                colon_token: token::Colon::default(),
+                ty: Box::new(ty),
+            });
+        }
+    }
+}
+
+// The lifetimes the impl declared in a typed self, by reference and by segment. They are
+// taken out of the registered types, whose generated lifetimes take their place, and stand
+// in the method's generics as user lifetimes until `set_impl_lifetimes`.
+fn take_impl_lifetimes(sig: &mut Signature) -> Option<Vec<Vec<Lifetime>>> {
+    let self_ty = sig.inputs.iter_mut().find_map(|input| match input {
+        FnArg::Typed(PatType {
+            pat: box Pat::Ident(PatIdent { ident, .. }),
+            ty,
+            ..
+        }) if ident == "self" => Some(ty),
+        _ => None,
+    })?;
+
+    let mut folder = SelfLifetimes {
+        slots: vec![],
+        take: true,
+    };
+    **self_ty = folder.fold_type((**self_ty).clone());
+
+    let mut lifetimes = concat(folder.slots.clone());
+    lifetimes.sort_by_key(|lt| lt.to_string());
+    lifetimes.dedup();
+    for lt in lifetimes.into_iter().rev() {
+        if lt.ident != "static" && !sig.generics.lifetimes().any(|lf_def| lf_def.lifetime == lt) {
+            sig.generics
+                .params
+                .insert(0, GenericParam::from(LifetimeDef::new(lt)));
+        }
+    }
+
+    Some(folder.slots)
+}
+
+// The generated lifetimes of the self type go back to the lifetimes of the impl, which leave
+// the method's generics, their bounds go into the where clause.
+fn set_impl_lifetimes(sig: &mut Signature, impl_lifetimes: Vec<Vec<Lifetime>>) {
+    let self_ty = sig.inputs.iter_mut().find_map(|input| match input {
+        FnArg::Typed(PatType {
+            pat: box Pat::Ident(PatIdent { ident, .. }),
+            ty,
+            ..
+        }) if ident == "self" => Some(ty),
+        _ => None,
+    });
+    let mut folder = SelfLifetimes {
+        slots: vec![],
+        take: false,
+    };
+    folder.fold_type((**self_ty.unwrap()).clone());
+
+    let impl_names = concat(impl_lifetimes.clone())
+        .iter()
+        .map(|lt| lt.to_string())
+        .collect::<Vec<_>>();
+    let generated = sig
+        .generics
+        .lifetimes()
+        .map(|lf_def| lf_def.lifetime.to_string())
+        .filter(|name| !impl_names.contains(name))
+        .collect::<Vec<_>>();
+
+    let mut renames = HashMap::new();
+    let mut predicates: Vec<WherePredicate> = vec![];
+    for (impl_lts, lts) in impl_lifetimes.iter().zip(folder.slots.iter()) {
+        for (impl_lt, lt) in impl_lts.iter().zip(lts.iter()) {
+            let name = lt.to_string();
+            if impl_lt == lt {
+                continue;
+            }
+            if generated.contains(&name) && !renames.contains_key(&name) {
+                renames.insert(name, impl_lt.to_string());
+            } else {
+                // the same lifetime in two places of the impl, or 'static
+                predicates.push(parse_quote!(#impl_lt: #lt));
+                if lt.ident != "static" {
+                    predicates.push(parse_quote!(#lt: #impl_lt));
+                }
+            }
+        }
+    }
+    *sig = LifetimeRenamer { renames }.fold_signature(sig.clone());
+
+    let mut params: punctuated::Punctuated<GenericParam, token::Comma> =
+        punctuated::Punctuated::new();
+    for gp in sig.generics.params.clone().into_iter() {
+        match gp {
+            GenericParam::Lifetime(lf_def) if impl_names.contains(&lf_def.lifetime.to_string()) => {
+                if !lf_def.bounds.is_empty() {
+                    let lifetime = lf_def.lifetime;
+                    let bounds = lf_def.bounds;
+                    predicates.push(parse_quote!(#lifetime: #bounds));
+                }
+            }
+            gp => params.push(gp),
+        }
+    }
+    sig.generics.params = params;
+
+    if !predicates.is_empty() {
+        sig.generics
+            .make_where_clause()
+            .predicates
+            .extend(predicates);
+    }
+}
+
+struct SelfLifetimes {
+    slots: Vec<Vec<Lifetime>>,
+    take: bool,
+}
+
+impl Fold for SelfLifetimes {
+    fn fold_type_reference(&mut self, tr: TypeReference) -> TypeReference {
+        self.slots.push(tr.lifetime.iter().cloned().collect());
+        fold::fold_type_reference(self, tr)
+    }
+
+    fn fold_path_segment(&mut self, mut segment: PathSegment) -> PathSegment {
+        let registered = !get_lifetime_coords(segment.ident.to_string()).is_empty();
+        let mut slot = vec![];
+        if let PathArguments::AngleBracketed(ab) = &mut segment.arguments {
+            if registered {
+                slot = ab
+                    .args
+                    .iter()
+                    .filter_map(|arg| match arg {
+                        GenericArgument::Lifetime(lt) => Some(lt.clone()),
+                        _ => None,
+                    })
+                    .collect();
+            }
+            if self.take {
+                ab.args = ab
+                    .args
+                    .clone()
+                    .into_iter()
+                    .filter(|arg| !registered || !matches!(arg, GenericArgument::Lifetime(_)))
+                    .collect();
+            }
+            if ab.args.is_empty() {
+                segment.arguments = PathArguments::None;
+            }
+        }
+        self.slots.push(slot);
+
+        fold::fold_path_segment(self, segment)
+    }
+}
+
+// `extern "C" { fn get(b: &Buf) -> &u8; }`, every fn is handled like `macro_fn`,
+// with the edges of its own `lifetime` attributes.
+fn macro_foreign_mod(mut foreign_mod: ItemForeignMod) -> TokenStream {
+    for item in foreign_mod.items.iter_mut() {
+        match item {
+            ForeignItem::Fn(fn_) => {
+                let mut statements = vec![];
+                for attr in fn_.attrs.iter() {
+                    if attr.path.is_ident("lifetime") {
+                        let group: Group = syn::parse2(attr.tokens.clone()).unwrap();
+                        statements.extend(get_lifetime_statements(group.stream()));
+                    }
+                }
+                fn_.attrs.retain(|attr| !attr.path.is_ident("lifetime"));
+
+                let renames = match set_sig_lifetime_bounds(statements, &mut fn_.sig) {
+                    Ok(renames) => renames,
+                    Err(error) => return error.to_compile_error().into(),
+                };
+                *fn_ = LifetimeRenamer { renames }.fold_foreign_item_fn(fn_.clone());
+            }
+            ForeignItem::Static(static_) => {
+                let renames = set_static_lifetime_symbols(&mut static_.ty);
+                *static_ = LifetimeRenamer { renames }.fold_foreign_item_static(static_.clone());
+            }
+            _ => (),
+        }
+    }
+
+    quote!(#foreign_mod).into()
+}
+
+fn set_sig_lifetime_bounds(
+    args: Vec<String>,
+    sig: &mut Signature,
+) -> Result<HashMap<String, String>> {
     let symbol_generator = &mut SymbolGenerator::new(String::from("f_"));
 
     let mut coords = vec![];
+    let mut paths = vec![];
+    let mut carried_edges = vec![];
 
-    // fn_.generics
-    let origins = vec![ROrigin::Generics(&mut fn_.sig.generics)];
+    // sig.generics
+    let origins = vec![ROrigin::Generics(&mut sig.generics)];
     let mut digrphs = get_ref_digrphs("generics".to_string(), origins);
     // set lifetime symbols
-    set_lifetime_symbols(Some(&mut fn_.sig.generics), &mut digrphs, symbol_generator);
+    set_lifetime_symbols(Some(&mut sig.generics), &mut digrphs, symbol_generator);
     // coords
     coords.extend(concat(digrphs.iter().map(|digrph| digrph.get_coords())));
-
-    // fn_.sig.inputs, fn_.sig.output
-    let origins = vec![
-        ROrigin::FnInputs(&mut fn_.sig.inputs),
-        ROrigin::FnOutput(&mut fn_.sig.output),
-    ];
+    paths.extend(concat(digrphs.iter().map(|digrph| digrph.get_paths())));
+    carried_edges.extend(concat(
+        digrphs.iter().map(|digrph| digrph.get_carried_edges()),
+    ));
+
+    // sig.inputs, sig.output
+    let output = match sig.asyncness {
+        Some(_) => ROrigin::AsyncFnOutput(&mut sig.output),
+        None => ROrigin::FnOutput(&mut sig.output),
+    };
+    let origins = vec![ROrigin::FnInputs(&mut sig.inputs), output];
     let mut digrphs = get_ref_digrphs("".to_string(), origins);
     // set lifetime symbols
-    set_lifetime_symbols(Some(&mut fn_.sig.generics), &mut digrphs, symbol_generator);
+    let scoped = set_lifetime_symbols(Some(&mut sig.generics), &mut digrphs, symbol_generator);
     // coords
     coords.extend(concat(digrphs.iter().map(|digrph| digrph.get_coords())));
+    paths.extend(concat(digrphs.iter().map(|digrph| digrph.get_paths())));
+    carried_edges.extend(concat(
+        digrphs.iter().map(|digrph| digrph.get_carried_edges()),
+    ));
+    carried_edges.extend(concat(
+        digrphs.iter().map(|digrph| digrph.get_capture_edges()),
+    ));
+
+    // edges, without any the fn follows the elision rules
+    let groups = get_groups("".to_string(), &args);
+    let mut edges = carried_edges;
+    if args.is_empty() {
+        edges.extend(get_elided_edges(&digrphs));
+    } else {
+        let arg_edges = concat(
+            args.into_iter()
+                .map(|arg| get_edges("".to_string(), arg, &groups)),
+        );
+        if sig.asyncness.is_some() {
+            check_async_edges("", &arg_edges)?;
+        }
+        edges.extend(arg_edges);
+    }
 
-    // edges
-    let edges = concat(args.into_iter().map(|arg| get_edges("".to_string(), arg)));
-
-    set_generic_lifetime_bounds(
-        fn_.sig
-            .generics
+    let mut renames = set_generic_lifetime_bounds(
+        sig.generics
             .params
             .iter_mut()
-            .filter(|gp| match gp {
-                GenericParam::Lifetime(_) => true,
-                _ => false,
-            })
+            .map(|gp| (String::new(), gp))
             .collect::<Vec<_>>(),
         coords,
+        paths,
         edges,
+        groups,
     );
+    set_scoped_lifetimes(&mut sig.generics, scoped, &mut renames);
 
-    quote!(#fn_).into()
+    Ok(renames)
 }
 
 fn macro_trait(mut trait_: ItemTrait) -> TokenStream {
@@ -275,150 +1002,1050 @@ fn macro_trait(mut trait_: ItemTrait) -> TokenStream {
     }
 
     set_lifetime_symbols(Some(&mut trait_.generics), &mut digrphs, symbol_generator);
-    set_lifetime_coords(name, &mut digrphs);
+    set_lifetime_coords(name, &digrphs);
+
+    let trait_ = LifetimeRenamer {
+        renames: HashMap::new(),
+    }
+    .fold_item_trait(trait_);
 
     quote!(#trait_).into()
 }
 
-fn get_edges(namespace: String, edges: String) -> Vec<(String, u8, String, u8)> {
-    let re = Regex::new(r"([a-zA-Z_][a-zA-Z0-9_!]*(?:\.[a-zA-Z_][a-zA-Z0-9_]*|\.(?:[1-9]\d*|0)|\[[a-zA-Z_][a-zA-Z0-9_]*(?:,(?:[1-9]\d*|0))?\])*)\(((?:[1-9]\d*|0)(?:,(?:[1-9]\d*|0))*)\)|([a-zA-Z_][a-zA-Z0-9_!]*(?:\.[a-zA-Z_][a-zA-Z0-9_]*|\.(?:[1-9]\d*|0)|\[[a-zA-Z_][a-zA-Z0-9_]*(?:,(?:[1-9]\d*|0))?\])*)|\(((?:[1-9]\d*|0)(?:,(?:[1-9]\d*|0))*)\)").unwrap();
-
-    let coord_groups = edges
-        .split_whitespace()
-        .collect::<String>()
-        .split("->")
-        .map(|coord_group| {
-            re.captures_iter(coord_group).fold(vec![], |mut r, caps| {
-                let name = caps
-                    .get(1)
-                    .or(caps.get(3))
-                    .map_or("Output!", |cap| cap.as_str());
-                let name = Regex::new(r"\[([^,\[\]]+)\]")
-                    .unwrap()
-                    .replace(name, |caps: &Captures| format!("[{},0]", &caps[1]));
-
-                let indexs: Vec<u8> = caps.get(2).or(caps.get(4)).map_or(vec![0], |cap| {
-                    cap.as_str()
-                        .split(",")
-                        .map(|i| i.parse().unwrap())
-                        .collect()
-                });
-
-                // $ is end
-                r.extend(
-                    indexs
-                        .into_iter()
-                        .map(move |index| (format!("{}$", name), index.clone())),
-                );
-                r
-            })
+// `#[lifetime("x -> (0)", "y -> (0)")]` or `#[lifetime(group g = x, y; g -> (0))]`,
+// statements are separated by `;` in both forms.
+fn get_lifetime_statements(tokens: proc_macro2::TokenStream) -> Vec<String> {
+    let literals = tokens
+        .clone()
+        .into_iter()
+        .filter_map(|token| match token {
+            TokenTree::Literal(li) => Some(li.to_string().trim_matches('"').to_string()),
+            _ => None,
         })
-        .collect::<Vec<Vec<(String, u8)>>>();
+        .collect::<Vec<_>>();
 
-    let mut edges = vec![];
+    let statements = if literals.is_empty() {
+        vec![tokens.to_string()]
+    } else {
+        literals
+    };
+
+    concat(statements.iter().map(|statement| {
+        statement
+            .split(";")
+            .filter(|statement| !statement.trim().is_empty())
+            .map(|statement| statement.to_string())
+            .collect::<Vec<_>>()
+    }))
+}
 
-    for (i, coord_group) in (&coord_groups[1..]).iter().enumerate() {
-        for coord_b in coord_group {
-            for coord_a in &coord_groups[i] {
-                edges.push((
-                    if coord_a.0.starts_with("self.") {
-                        coord_a.0.clone()
-                    } else {
-                        if namespace.is_empty() {
-                            coord_a.0.clone()
-                        } else {
-                            format!("{}/{}", namespace, coord_a.0)
-                        }
-                    },
-                    coord_a.1,
-                    if coord_b.0.starts_with("self.") {
-                        coord_b.0.clone()
-                    } else {
-                        if namespace.is_empty() {
-                            coord_b.0.clone()
-                        } else {
-                            format!("{}/{}", namespace, coord_b.0)
-                        }
-                    },
-                    coord_b.1,
-                ));
-            }
+// `group name = x, self.x(0), other_group`
+fn get_groups(namespace: String, statements: &[String]) -> Vec<(String, Vec<(String, u8)>)> {
+    let re = Regex::new(r"^\s*group\s+([a-zA-Z_][a-zA-Z0-9_]*)\s*=(.*)$").unwrap();
+
+    let mut groups = vec![];
+    for statement in statements {
+        if let Some(caps) = re.captures(statement) {
+            let members = caps[2].split_whitespace().collect::<String>();
+            let members = get_coords_from_text(&namespace, &members, &groups);
+
+            groups.push((caps[1].to_string(), members));
         }
     }
 
-    edges
+    groups
 }
 
-fn set_generic_lifetime_bounds(
-    //mut generic_lifetimes_map: HashMap<(String, u8), &mut GenericParam>,
-    gps: Vec<&mut GenericParam>,
-    coords: Vec<(String, u8)>,
-    edges: Vec<(String, u8, String, u8)>,
-) {
-    println!("\n======================================\n");
-    println!("coords: {:?}", coords);
-    println!("edges: {:?}", edges);
+fn get_coords_from_text(
+    namespace: &String,
+    text: &str,
+    groups: &[(String, Vec<(String, u8)>)],
+) -> Vec<(String, u8)> {
+    let name = r"[a-zA-Z_][a-zA-Z0-9_!]*(?:\.[a-zA-Z_][a-zA-Z0-9_!]*(?:/[a-zA-Z_][a-zA-Z0-9_!]*)?|\.(?:[1-9]\d*|0)|\[[a-zA-Z_][a-zA-Z0-9_]*(?:,(?:[1-9]\d*|0))?\])*(?:\.\*)?";
+    let index = r"(?:\*|\+|(?:[1-9]\d*|0)(?:\.\.(?:[1-9]\d*|0))?)";
+    let re = Regex::new(&format!(
+        r"({0})\(({1}(?:,{1})*)\)|({0})|\(({1}(?:,{1})*)\)|('[a-zA-Z_][a-zA-Z0-9_]*)",
+        name, index
+    ))
+    .unwrap();
+
+    let full_name = |name: &str| {
+        if name.starts_with("self.") || namespace.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", namespace, name)
+        }
+    };
 
-    let mut generic_lifetimes_map = HashMap::new();
+    re.captures_iter(text).fold(vec![], |mut r, caps| {
+        // 'static and user lifetimes are not coords
+        if let Some(lifetime) = caps.get(5) {
+            r.push((lifetime.as_str().to_string(), 0));
+            return r;
+        }
 
-    for (coord, gp) in coords.iter().zip(gps.into_iter().filter(|gp| match gp {
-        GenericParam::Lifetime(_) => true,
-        _ => false,
-    })) {
-        generic_lifetimes_map.insert((*coord).clone(), gp);
-    }
+        // a group expands to its members
+        if let Some(group) = caps.get(3) {
+            if let Some((_, members)) = groups.iter().find(|(name, _)| name == group.as_str()) {
+                r.extend(members.iter().cloned());
+                return r;
+            }
+        }
 
-    let abbr_names_trie = get_abbr_names_trie(coords);
-    //println!("trie: {:#?}", abbr_names_trie);
+        let name = caps
+            .get(1)
+            .or(caps.get(3))
+            .map_or("Output!", |cap| cap.as_str());
+        let name = Regex::new(r"\[([^,\[\]]+)\]")
+            .unwrap()
+            .replace(name, |caps: &Captures| format!("[{},0]", &caps[1]));
+        let name = full_name(&name);
+
+        let indexs: Vec<&str> = caps
+            .get(2)
+            .or(caps.get(4))
+            .map_or(vec!["0"], |cap| cap.as_str().split(",").collect());
+
+        for index in indexs {
+            // `.*` selects every lifetime beneath the name, expanded later
+            // `+` is the capture bound of an `impl Trait`
+            if index == "+" {
+                r.push((format!("{}.+$", name), 0));
+            } else if index == "*" || name.ends_with(".*") {
+                let name = name.trim_end_matches(".*");
+                r.push((format!("{}.*$", name), 0));
+            } else if let Some((start, end)) = index.split_once("..") {
+                let (start, end): (u8, u8) = (start.parse().unwrap(), end.parse().unwrap());
 
-    for (name1, index1, name2, index2) in edges {
-        // change abbr name to full name
-        //println!(
-        //    "before edge: ({}, {}) -> ({}, {})",
-        //    name1, index1, name2, index2
-        //);
-        let name1 = abbr_names_trie.get(&name1.split(".").collect::<Vec<_>>());
-        //println!("name1: {:?}", name1);
-        let name1 = (*name1.unwrap()).clone();
-        let name2 = abbr_names_trie.get(&name2.split(".").collect::<Vec<_>>());
-        //println!("name2: {:?}", name2);
-        let name2 = (*name2.unwrap()).clone();
-        //println!(
-        //    "after edge: ({}, {}) -> ({}, {})",
-        //    name1, index1, name2, index2
-        //);
-
-        let param_b = generic_lifetimes_map.get(&(name2, index2)).unwrap();
-        match param_b {
-            GenericParam::Lifetime(ref lf_def_b) => {
-                //println!("lf_def_b: {:?}", lf_def_b);
-                let symbol = format!("'{}", lf_def_b.lifetime.ident);
-
-                let param_a = generic_lifetimes_map.get_mut(&(name1, index1)).unwrap();
-                match param_a {
-                    GenericParam::Lifetime(ref mut lf_def_a) => {
-                        //println!("lf_def_a: {:?}", lf_def_a);
-                        let lf = Lifetime::new(&symbol, Span::call_site());
-                        lf_def_a.bounds.push(lf);
-                    }
-                    _ => (),
+                // $ is end
+                r.extend((start..end).map(|index| (format!("{}$", name), index)));
+            } else {
+                // $ is end
+                r.push((format!("{}$", name), index.parse().unwrap()));
+            }
+        }
+        r
+    })
+}
+
+fn get_edges(
+    namespace: String,
+    edges: String,
+    groups: &[(String, Vec<(String, u8)>)],
+) -> Vec<Edge> {
+    // group definitions are collected by `get_groups`
+    if edges.trim_start().starts_with("group ") {
+        return vec![];
+    }
+
+    let edges = edges.split_whitespace().collect::<String>();
+
+    // `a -> b`: a outlives b, `a = b` or `a <-> b`: a and b are the same lifetime
+    let relation_re = Regex::new(r"<->|->|=").unwrap();
+    let relations = relation_re
+        .find_iter(&edges)
+        .map(|m| m.as_str())
+        .collect::<Vec<_>>();
+
+    let coord_groups = relation_re
+        .split(&edges)
+        .map(|coord_group| get_coords_from_text(&namespace, coord_group, groups))
+        .collect::<Vec<Vec<(String, u8)>>>();
+
+    let mut edges = vec![];
+
+    for (i, coord_group) in (&coord_groups[1..]).iter().enumerate() {
+        for coord_b in coord_group {
+            for coord_a in &coord_groups[i] {
+                edges.push((coord_a.0.clone(), coord_a.1, coord_b.0.clone(), coord_b.1));
+
+                if relations[i] != "->" {
+                    edges.push((coord_b.0.clone(), coord_b.1, coord_a.0.clone(), coord_a.1));
+                }
+            }
+        }
+    }
+
+    edges
+}
+
+// The future of an async fn captures every input lifetime by itself, its edges only bound
+// what the output borrows from: each one needs Output!, a user lifetime or 'static at an end.
+fn check_async_edges(namespace: &str, edges: &[Edge]) -> Result<()> {
+    let output = format_digrph_name(namespace.to_string(), "Output!".to_string());
+    let is_bound = |name: &String| name.starts_with(&output) || name.starts_with('\'');
+    match edges.iter().find(|(name1, _, name2, _)| !is_bound(name1) && !is_bound(name2)) {
+        Some((name1, index1, name2, index2)) => Err(Error::new(
+            Span::call_site(),
+            format!(
+                "an async fn captures its inputs, `{}({}) -> {}({})` must start or end on Output! or a lifetime",
+                name1.trim_end_matches("$"),
+                index1,
+                name2.trim_end_matches("$"),
+                index2
+            ),
+        )),
+        None => Ok(()),
+    }
+}
+
+// Rust's lifetime elision rules: the lifetime of `&self`, or else the only input lifetime,
+// outlives every lifetime of the output. Async fns elide the same way.
+fn get_elided_edges(digrphs: &[RDigrph]) -> Vec<Edge> {
+    let (outputs, inputs): (Vec<_>, Vec<_>) = digrphs
+        .iter()
+        .partition(|digrph| digrph.name.ends_with("Output!"));
+
+    let output = match outputs.first() {
+        Some(output) if !output.get_coords().is_empty() => output,
+        _ => return vec![],
+    };
+
+    let receiver = inputs
+        .iter()
+        .find(|digrph| digrph.name == "self" || digrph.name.ends_with("/self"))
+        .and_then(|digrph| digrph.get_coords().into_iter().next());
+    let input_coords = concat(inputs.iter().map(|digrph| digrph.get_coords()));
+
+    let input = match receiver {
+        Some(coord) => coord,
+        None if input_coords.len() == 1 => input_coords[0].clone(),
+        None => return vec![],
+    };
+
+    vec![(input.0, input.1, format!("{}.*$", output.name), 0)]
+}
+
+// The bounds of a struct or enum between its generated lifetimes, returned as edges between
+// its coords so every item using the type can repeat them. Merged lifetimes become one param,
+// the first of them, and the coords without a param of their own are registered.
+// The renames returned merge the lifetimes and give group names to them.
+fn set_type_lifetime_bounds(
+    name: String,
+    generics: &mut Generics,
+    digrphs: &[RDigrph],
+    scoped: Vec<(String, *mut Option<BoundLifetimes>)>,
+    args: Vec<String>,
+) -> (Vec<Edge>, HashMap<String, String>) {
+    let coords = concat(digrphs.iter().map(|digrph| digrph.get_coords()));
+    let paths = concat(digrphs.iter().map(|digrph| digrph.get_paths()));
+
+    // the options of the fields and variants come first, they may define groups
+    let mut statements = get_option_statements(digrphs);
+    statements.extend(args);
+
+    let groups = get_groups(name.clone(), &statements);
+    let mut edges = concat(digrphs.iter().map(|digrph| digrph.get_carried_edges()));
+    edges.extend(concat(
+        statements
+            .into_iter()
+            .map(|statement| get_edges(name.clone(), statement, &groups)),
+    ));
+
+    let renames = set_generic_lifetime_bounds(
+        generics
+            .params
+            .iter_mut()
+            .map(|gp| (String::new(), gp))
+            .collect::<Vec<_>>(),
+        coords.clone(),
+        paths,
+        edges,
+        groups,
+    );
+
+    // the generated lifetimes are the last ones, in the order of the coords
+    let lifetimes = generics
+        .lifetimes()
+        .map(|lf_def| lf_def.lifetime.to_string())
+        .collect::<Vec<_>>();
+    let (user, generated) = lifetimes.split_at(lifetimes.len() - coords.len());
+
+    // a lifetime merged into a hand-written one outlives it both ways, the users of the type
+    // can't name the hand-written one
+    let mut type_renames: HashMap<String, String> = HashMap::new();
+    for symbol in generated.iter() {
+        match renames.get(symbol) {
+            Some(merged) if user.contains(merged) => {
+                for lf_def in generics.lifetimes_mut() {
+                    let lifetime = lf_def.lifetime.to_string();
+                    if &lifetime == symbol {
+                        lf_def.bounds.push(Lifetime::new(merged, Span::call_site()));
+                    } else if &lifetime == merged {
+                        lf_def.bounds.push(Lifetime::new(symbol, Span::call_site()));
+                    }
+                }
+            }
+            Some(merged) => {
+                type_renames.insert(symbol.clone(), merged.clone());
+            }
+            None => (),
+        }
+    }
+
+    let names = generated
+        .iter()
+        .map(|symbol| type_renames.get(symbol).unwrap_or(symbol).clone())
+        .collect::<Vec<_>>();
+    let firsts = names
+        .iter()
+        .map(|name| names.iter().position(|other| other == name).unwrap())
+        .collect::<Vec<_>>();
+    let is_scoped = |i: usize| scoped.iter().any(|(symbol, _)| *symbol == generated[i]);
+    let get_coord = |lt: &Lifetime| -> Option<(String, u8)> {
+        if lt.ident == "static" {
+            return Some(("'static".to_string(), 0));
+        }
+        let lt = lt.to_string();
+        names
+            .iter()
+            .position(|name| *name == lt)
+            .filter(|&i| !is_scoped(i))
+            .map(|i| coords[i].clone())
+    };
+
+    // a higher-ranked lifetime is no param of the type, for its users it collapses into
+    // 'static like a field with the `static` option
+    let mut edges = vec![];
+    for (i, lf_def) in generics.lifetimes().skip(user.len()).enumerate() {
+        let (name1, index1) = coords[i].clone();
+        if names[i] == "'static" || is_scoped(i) {
+            edges.push((name1.clone(), index1, "'static".to_string(), 0));
+            edges.push(("'static".to_string(), 0, name1, index1));
+        } else if firsts[i] != i {
+            let (name2, index2) = coords[firsts[i]].clone();
+            edges.push((name1.clone(), index1, name2.clone(), index2));
+            edges.push((name2, index2, name1, index1));
+        } else {
+            edges.extend(
+                lf_def
+                    .bounds
+                    .iter()
+                    .filter_map(get_coord)
+                    .map(|(name2, index2)| (name1.clone(), index1, name2, index2)),
+            );
+        }
+    }
+
+    set_lifetime_params(
+        name,
+        (0..names.len())
+            .map(|i| firsts[i] == i && names[i] != "'static" && !is_scoped(i))
+            .collect(),
+    );
+
+    set_scoped_lifetimes(generics, scoped, &mut type_renames);
+
+    (edges, type_renames)
+}
+
+// `static` is 'static, `name = "src"` names the lifetime of a field or variant and
+// `share = "src"` makes it the same as the one named `src`, or else the field `src`.
+fn get_option_statements(digrphs: &[RDigrph]) -> Vec<String> {
+    let mut statements = vec![];
+    let mut names: Vec<(String, Vec<String>)> = vec![];
+    let mut shares = vec![];
+
+    for digrph in digrphs.iter() {
+        let field = digrph.name.rsplit("/").next().unwrap().to_string();
+
+        for (key, value) in digrph.options.iter() {
+            match key.as_str() {
+                "static" => statements.push(format!("{}.* = 'static", field)),
+                "name" => match names.iter_mut().find(|(name, _)| name == value) {
+                    Some((_, fields)) => fields.push(field.clone()),
+                    None => names.push((value.clone(), vec![field.clone()])),
+                },
+                "share" => shares.push((value.clone(), field.clone())),
+                _ => (),
+            }
+        }
+    }
+
+    for (value, field) in shares {
+        match names.iter_mut().find(|(name, _)| *name == value) {
+            Some((_, fields)) => fields.push(field),
+            None => statements.push(format!("{} = {}", field, value)),
+        }
+    }
+
+    for (name, fields) in names {
+        statements.push(format!("group {} = {}", name, fields.join(", ")));
+        if fields.len() > 1 {
+            statements.push(fields.join(" = "));
+        }
+    }
+
+    statements
+}
+
+fn set_generic_lifetime_bounds(
+    //mut generic_lifetimes_map: HashMap<(String, u8), &mut GenericParam>,
+    gps: Vec<(String, &mut GenericParam)>,
+    coords: Vec<(String, u8)>,
+    paths: Vec<(String, u8)>,
+    edges: Vec<Edge>,
+    groups: Vec<(String, Vec<(String, u8)>)>,
+) -> HashMap<String, String> {
+    let (mut gps, mut tps): (Vec<_>, Vec<_>) = gps
+        .into_iter()
+        .filter(|(_, gp)| !matches!(gp, GenericParam::Const(_)))
+        .partition(|(_, gp)| matches!(gp, GenericParam::Lifetime(_)));
+
+    // node 0 is 'static, followed by the user lifetimes and the generated ones,
+    // the generated lifetimes are always the last ones, type params come at the end
+    let external = gps.len() - coords.len() + 1;
+    let symbols = std::iter::once("'static".to_string())
+        .chain(gps.iter().map(|(_, gp)| match gp {
+            GenericParam::Lifetime(lf_def) => format!("'{}", lf_def.lifetime.ident),
+            _ => unreachable!(),
+        }))
+        .collect::<Vec<_>>();
+    let tp_names = tps
+        .iter()
+        .map(|(namespace, gp)| match gp {
+            GenericParam::Type(tp) => format_digrph_name(namespace.clone(), tp.ident.to_string()),
+            _ => unreachable!(),
+        })
+        .collect::<Vec<_>>();
+    let n = symbols.len() + tp_names.len();
+
+    // both `coords` and `paths` point into `symbols` by position
+    let generic_lifetimes_map: HashMap<(String, u8), usize> = coords
+        .iter()
+        .cloned()
+        .zip(external..symbols.len())
+        .collect();
+    let generic_paths_map: HashMap<(String, u8), usize> =
+        paths.iter().cloned().zip(external..symbols.len()).collect();
+
+    let abbr_names_trie = get_abbr_names_trie(coords.clone());
+    let abbr_paths_trie = get_abbr_names_trie(paths.clone());
+    //println!("trie: {:#?}", abbr_names_trie);
+
+    // structured paths first, flat coords as fallback
+    let get_coord_indexes = |name: &str, index: u8| -> Option<Vec<usize>> {
+        // `name.*` is every coord beneath `name`
+        if name.ends_with(".*$") {
+            let prefix = name.trim_end_matches(".*$").split(".").collect::<Vec<_>>();
+            let indexes = (0..coords.len())
+                .filter(|i| {
+                    is_coord_beneath(&coords[*i].0, &prefix)
+                        || is_coord_beneath(&paths[*i].0, &prefix)
+                })
+                .map(|i| external + i)
+                .collect::<Vec<_>>();
+
+            return Some(indexes).filter(|indexes| !indexes.is_empty());
+        }
+
+        let cells = name.split(".").collect::<Vec<_>>();
+
+        let path_index = match abbr_paths_trie.get(&cells) {
+            UniqueOption::Some(path) => generic_paths_map.get(&((*path).clone(), index)).cloned(),
+            _ => None,
+        };
+        let coord_index = match abbr_names_trie.get(&cells) {
+            UniqueOption::Some(coord) => generic_lifetimes_map
+                .get(&((*coord).clone(), index))
+                .cloned(),
+            _ => None,
+        };
+
+        // `x.Some.0` is, abbreviated, the field 0 of the type inside the variant `Some`, e.g.
+        // `x.Some[Type,0].0`, before the field 0 of the variant, which is also `x.Some(0)`
+        coord_index.or(path_index).map(|i| vec![i])
+    };
+
+    let get_gp_indexes = |name: &String, index: u8| -> Vec<usize> {
+        // 'static or a lifetime declared next to the generated ones
+        if name.starts_with("'") {
+            match symbols[..external].iter().position(|symbol| symbol == name) {
+                Some(i) => return vec![i],
+                None => panic!("unknown lifetime: {}", name),
+            }
+        }
+
+        // type params, declared on the method first, then on the impl
+        let bare = name.trim_end_matches("$");
+        if let Some(i) = tp_names.iter().position(|tp| tp == bare).or_else(|| {
+            let bare = bare.rsplit("/").next().unwrap();
+            tp_names.iter().position(|tp| tp == bare)
+        }) {
+            return vec![symbols.len() + i];
+        }
+
+        // coords of the method first, then the ones of the impl (its generics)
+        let namespace_re = Regex::new(r"^[a-zA-Z_][a-zA-Z0-9_]*/").unwrap();
+        get_coord_indexes(name, index)
+            .or_else(|| {
+                if namespace_re.is_match(name) {
+                    get_coord_indexes(&namespace_re.replace(name, ""), index)
+                } else {
+                    None
+                }
+            })
+            .unwrap_or_else(|| panic!("unknown lifetime coord: {}({})", name, index))
+    };
+
+    let edges = concat(edges.iter().map(|(name1, index1, name2, index2)| {
+        // change abbr name to full name
+        let indexes2 = get_gp_indexes(name2, *index2);
+        concat(
+            get_gp_indexes(name1, *index1)
+                .into_iter()
+                .map(|i1| indexes2.iter().map(|i2| (i1, *i2)).collect::<Vec<_>>()),
+        )
+    }));
+
+    if let Some((_, i)) = edges.iter().find(|(_, i2)| *i2 >= symbols.len()) {
+        panic!(
+            "type param {} can only outlive lifetimes",
+            tp_names[i - symbols.len()]
+        );
+    }
+
+    // every strongly connected component collapses into its first lifetime,
+    // so 'static and user lifetimes win over generated ones
+    let sccs = get_lifetime_sccs(n, &edges);
+
+    // a group collapsed into one generated lifetime lends it its name, if the name is free
+    let mut names = symbols.clone();
+    names.extend(tp_names.iter().cloned());
+    for (group, members) in groups.iter() {
+        let members = concat(
+            members
+                .iter()
+                .map(|(name, index)| get_gp_indexes(name, *index)),
+        );
+        let scc = sccs[members[0]];
+        let name = format!("'{}", group);
+
+        if scc >= external
+            && scc < symbols.len()
+            && members.iter().all(|i| sccs[*i] == scc)
+            && !names.contains(&name)
+        {
+            names[scc] = name;
+        }
+    }
+
+    let mut renames = HashMap::new();
+    for (i, scc) in sccs[..symbols.len()].iter().enumerate() {
+        if names[*scc] != symbols[i] {
+            renames.insert(symbols[i].clone(), names[*scc].clone());
+        }
+    }
+
+    // deduplicated edges between components, without transitively implied ones,
+    // 'static outlives everything and needs no bounds
+    let mut edges = edges
+        .into_iter()
+        .map(|(i1, i2)| (sccs[i1], sccs[i2]))
+        .filter(|(i1, i2)| i1 != i2 && *i1 != 0)
+        .collect::<Vec<_>>();
+    edges.sort();
+    edges.dedup();
+
+    let reachable = get_lifetime_reachability(
+        n,
+        &edges
+            .iter()
+            .cloned()
+            .chain((1..symbols.len()).map(|i| (0, i)))
+            .collect::<Vec<_>>(),
+    );
+    let edges = edges
+        .iter()
+        .filter(|(i1, i2)| {
+            !edges
+                .iter()
+                .any(|(j1, j2)| j1 == i1 && j2 != i2 && reachable[*j2][*i2])
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+
+    for (i1, i2) in edges {
+        let lf = Lifetime::new(&names[i2], Span::call_site());
+
+        let param_a = if i1 < symbols.len() {
+            &mut gps[i1 - 1].1
+        } else {
+            &mut tps[i1 - symbols.len()].1
+        };
+        match param_a {
+            GenericParam::Lifetime(ref mut lf_def_a) => {
+                //println!("lf_def_a: {:?}", lf_def_a);
+                lf_def_a.bounds.push(lf);
+            }
+            GenericParam::Type(ref mut tp_a) => {
+                tp_a.bounds.push(TypeParamBound::Lifetime(lf));
+            }
+            _ => (),
+        }
+    }
+
+    renames
+}
+
+// Lifetimes of `Fn(&T) -> &T` bounds and bare fns are higher-ranked, they leave `generics` for
+// the `for<..>` of their bound. Higher-ranked lifetimes can't have bounds, so the related
+// ones become one lifetime, the ones merged into an outer lifetime stay outer. The scopes
+// only tell the lifetimes apart here, they may have moved since.
+fn set_scoped_lifetimes(
+    generics: &mut Generics,
+    scoped: Vec<(String, *mut Option<BoundLifetimes>)>,
+    renames: &mut HashMap<String, String>,
+) {
+    let scoped_names = scoped
+        .iter()
+        .map(|(symbol, _)| {
+            (
+                symbol.clone(),
+                renames.get(symbol).cloned().unwrap_or(symbol.clone()),
+            )
+        })
+        .collect::<Vec<_>>();
+    let scope_of = |symbol: &String| {
+        scoped
+            .iter()
+            .find(|(scoped_symbol, _)| scoped_symbol == symbol)
+            .map(|(_, scope)| *scope)
+    };
+
+    let mut names = scoped_names
+        .iter()
+        .map(|(_, name)| name.clone())
+        .filter(|name| scope_of(name).is_some())
+        .collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+
+    // union-find over the bounds between lifetimes of the same scope
+    let mut parents = names
+        .iter()
+        .cloned()
+        .map(|name| (name.clone(), name))
+        .collect::<HashMap<_, _>>();
+    fn find(parents: &HashMap<String, String>, name: &String) -> String {
+        let parent = &parents[name];
+        if parent == name {
+            name.clone()
+        } else {
+            find(parents, parent)
+        }
+    }
+    for lf_def in generics.lifetimes() {
+        let name_a = lf_def.lifetime.to_string();
+        if !names.contains(&name_a) {
+            continue;
+        }
+
+        for lt in lf_def.bounds.iter() {
+            let name_b = lt.to_string();
+            if names.contains(&name_b) && scope_of(&name_a) == scope_of(&name_b) {
+                let (root_a, root_b) = (find(&parents, &name_a), find(&parents, &name_b));
+                parents.insert(root_b, root_a);
+            }
+        }
+    }
+
+    // a lifetime alone in its scope is left to the elision rules, e.g. `Fn(&str) -> &str`
+    let roots = scoped_names
+        .iter()
+        .filter(|(_, name)| names.contains(name))
+        .map(|(_, name)| find(&parents, name))
+        .collect::<Vec<_>>();
+    let alone = |root: &String| roots.iter().filter(|other| *other == root).count() == 1;
+
+    for (symbol, name) in scoped_names.iter() {
+        if names.contains(name) {
+            let root = find(&parents, name);
+            if alone(&root) {
+                renames.insert(symbol.clone(), "'_".to_string());
+            } else if &root != symbol {
+                renames.insert(symbol.clone(), root);
+            } else {
+                renames.remove(symbol);
+            }
+        }
+    }
+
+    // the higher-ranked lifetimes leave the generics, with every bound on them
+    generics.params = generics
+        .params
+        .clone()
+        .into_iter()
+        .filter(|gp| match gp {
+            GenericParam::Lifetime(lf_def) => !names.contains(&lf_def.lifetime.to_string()),
+            _ => true,
+        })
+        .map(|mut gp| {
+            match gp {
+                GenericParam::Lifetime(ref mut lf_def) => {
+                    lf_def.bounds = lf_def
+                        .bounds
+                        .clone()
+                        .into_iter()
+                        .filter(|lt| !names.contains(&lt.to_string()))
+                        .collect();
+                }
+                GenericParam::Type(ref mut tp) => {
+                    tp.bounds = tp
+                        .bounds
+                        .clone()
+                        .into_iter()
+                        .filter(|tpb| match tpb {
+                            TypeParamBound::Lifetime(lt) => !names.contains(&lt.to_string()),
+                            _ => true,
+                        })
+                        .collect();
                 }
+                _ => (),
+            }
+            gp
+        })
+        .collect();
+}
+
+// A method's own lifetimes are only in scope in the method, the merges and the bounds of the
+// impl's params with them go into the method's where clause.
+fn set_method_lifetime_bounds(
+    impl_: &mut ItemImpl,
+    method_lifetimes: &[(String, HashMap<String, String>)],
+    renames: &mut HashMap<String, String>,
+) {
+    let method_of = |name: &String| {
+        method_lifetimes
+            .iter()
+            .position(|(_, lifetimes)| lifetimes.values().any(|unique| unique == name))
+    };
+    let mut predicates: Vec<Vec<WherePredicate>> = vec![vec![]; method_lifetimes.len()];
+
+    // merged lifetimes across the impl and a method outlive each other instead
+    for (symbol, name) in renames.clone() {
+        let index = match (method_of(&symbol), method_of(&name)) {
+            (Some(i), Some(j)) if i == j => continue,
+            (Some(i), _) | (None, Some(i)) => i,
+            (None, None) => continue,
+        };
+        renames.remove(&symbol);
+        let (lt_a, lt_b) = (
+            Lifetime::new(&symbol, Span::call_site()),
+            Lifetime::new(&name, Span::call_site()),
+        );
+        predicates[index].push(parse_quote!(#lt_a: #lt_b));
+        if name != "'static" {
+            predicates[index].push(parse_quote!(#lt_b: #lt_a));
+        }
+    }
+
+    // the bounds of the impl's params on them
+    for gp in impl_.generics.params.iter_mut() {
+        match gp {
+            GenericParam::Lifetime(lf_def) => {
+                let lt_a = lf_def.lifetime.clone();
+                lf_def.bounds = lf_def
+                    .bounds
+                    .clone()
+                    .into_iter()
+                    .filter(|lt_b| match method_of(&lt_b.to_string()) {
+                        Some(i) => {
+                            predicates[i].push(parse_quote!(#lt_a: #lt_b));
+                            false
+                        }
+                        None => true,
+                    })
+                    .collect();
+            }
+            GenericParam::Type(tp) => {
+                let ident = tp.ident.clone();
+                tp.bounds = tp
+                    .bounds
+                    .clone()
+                    .into_iter()
+                    .filter(|tpb| match tpb {
+                        TypeParamBound::Lifetime(lt_b) => match method_of(&lt_b.to_string()) {
+                            Some(i) => {
+                                predicates[i].push(parse_quote!(#ident: #lt_b));
+                                false
+                            }
+                            None => true,
+                        },
+                        _ => true,
+                    })
+                    .collect();
             }
             _ => (),
         }
     }
+
+    for (item, predicates) in impl_
+        .items
+        .iter_mut()
+        .filter(|item| matches!(item, ImplItem::Method(_)))
+        .zip(predicates)
+    {
+        if let ImplItem::Method(iim) = item {
+            if !predicates.is_empty() {
+                iim.sig
+                    .generics
+                    .make_where_clause()
+                    .predicates
+                    .extend(predicates);
+            }
+        }
+    }
+}
+
+// Maps every lifetime to the first lifetime of its strongly connected component.
+fn get_lifetime_sccs(n: usize, edges: &[(usize, usize)]) -> Vec<usize> {
+    let reachable = get_lifetime_reachability(n, edges);
+
+    (0..n)
+        .map(|i| {
+            (0..n)
+                .find(|j| reachable[i][*j] && reachable[*j][i])
+                .unwrap()
+        })
+        .collect()
+}
+
+// `reachable[a][b]` is true when `a` outlives `b` through zero or more edges.
+fn get_lifetime_reachability(n: usize, edges: &[(usize, usize)]) -> Vec<Vec<bool>> {
+    let mut reachable = vec![vec![false; n]; n];
+
+    for (i, row) in reachable.iter_mut().enumerate() {
+        let mut stack = vec![i];
+        row[i] = true;
+
+        while let Some(a) = stack.pop() {
+            for (_, b) in edges.iter().filter(|(a_, _)| *a_ == a) {
+                if !row[*b] {
+                    row[*b] = true;
+                    stack.push(*b);
+                }
+            }
+        }
+    }
+
+    reachable
+}
+
+// Renames the collapsed lifetimes and merges their generic params.
+struct LifetimeRenamer {
+    renames: HashMap<String, String>,
+}
+
+impl Fold for LifetimeRenamer {
+    fn fold_lifetime(&mut self, lt: Lifetime) -> Lifetime {
+        match self.renames.get(&lt.to_string()) {
+            Some(symbol) => Lifetime::new(symbol, lt.apostrophe),
+            None => lt,
+        }
+    }
+
+    // the lifetimes of a type macro go back from its path to its `'_`
+    fn fold_type_macro(&mut self, tm: TypeMacro) -> TypeMacro {
+        let mut tm = fold::fold_type_macro(self, tm);
+
+        let segment = tm.mac.path.segments.last_mut().unwrap();
+        if let PathArguments::AngleBracketed(ab) =
+            std::mem::replace(&mut segment.arguments, PathArguments::None)
+        {
+            let mut lifetimes = ab.args.into_iter().filter_map(|arg| match arg {
+                GenericArgument::Lifetime(lt) => Some(lt),
+                _ => None,
+            });
+            tm.mac.tokens = set_macro_lifetimes(tm.mac.tokens, &mut lifetimes);
+        }
+
+        tm
+    }
+
+    // a renamed lifetime leaves its `for<..>`
+    fn fold_bound_lifetimes(&mut self, mut bl: BoundLifetimes) -> BoundLifetimes {
+        bl.lifetimes = bl
+            .lifetimes
+            .into_iter()
+            .filter(|lf_def| !self.renames.contains_key(&lf_def.lifetime.to_string()))
+            .collect();
+
+        fold::fold_bound_lifetimes(self, bl)
+    }
+
+    fn fold_type_bare_fn(&mut self, tbf: TypeBareFn) -> TypeBareFn {
+        let mut tbf = fold::fold_type_bare_fn(self, tbf);
+        if matches!(tbf.lifetimes, Some(ref bl) if bl.lifetimes.is_empty()) {
+            tbf.lifetimes = None;
+        }
+
+        tbf
+    }
+
+    fn fold_trait_bound(&mut self, tb: TraitBound) -> TraitBound {
+        let mut tb = fold::fold_trait_bound(self, tb);
+        if matches!(tb.lifetimes, Some(ref bl) if bl.lifetimes.is_empty()) {
+            tb.lifetimes = None;
+        }
+
+        tb
+    }
+
+    fn fold_generics(&mut self, generics: Generics) -> Generics {
+        let mut generics = fold::fold_generics(self, generics);
+
+        let mut params: punctuated::Punctuated<GenericParam, token::Comma> =
+            punctuated::Punctuated::new();
+        for gp in generics.params.into_iter() {
+            if let GenericParam::Lifetime(lf_def) = &gp {
+                // collapsed into 'static
+                if lf_def.lifetime.ident == "static" {
+                    continue;
+                }
+
+                let merged = params.iter_mut().find_map(|merged| match merged {
+                    GenericParam::Lifetime(merged) if merged.lifetime == lf_def.lifetime => {
+                        Some(merged)
+                    }
+                    _ => None,
+                });
+
+                if let Some(merged) = merged {
+                    merged.bounds.extend(lf_def.bounds.iter().cloned());
+                    continue;
+                }
+            }
+
+            params.push(gp);
+        }
+
+        for gp in params.iter_mut() {
+            if let GenericParam::Lifetime(lf_def) = gp {
+                let lifetime = lf_def.lifetime.clone();
+                let mut bounds: punctuated::Punctuated<Lifetime, token::Add> =
+                    punctuated::Punctuated::new();
+                for bound in lf_def.bounds.iter() {
+                    if *bound != lifetime && !bounds.iter().any(|b| b == bound) {
+                        bounds.push(bound.clone());
+                    }
+                }
+                lf_def.bounds = bounds;
+            }
+        }
+
+        generics.params = params;
+        generics
+    }
+}
+
+// Items inside a block, e.g. of a fn without `#[lifetime]`, are registered as
+// `name@file:offset`, so they can't replace the crate-level `name`.
+fn get_registry_key(name: &str) -> String {
+    match get_nested_site() {
+        Some((file, offset)) => format!("{}@{}:{}", name, file.display(), offset),
+        None => name.to_string(),
+    }
+}
+
+// A use inside a block sees the nearest scoped item before it in a block still open, or else
+// the crate-level one. A crate-level use sees the crate-level one, or else a scoped one, e.g.
+// of a module or a `macro_rules!`.
+fn get_resolved_key(name: &str) -> String {
+    let prefix = format!("{}@", name);
+    let (crate_level, scoped): (Vec<_>, Vec<_>) = LIFETIME_COORDS_MAP
+        .lock()
+        .unwrap()
+        .keys()
+        .filter(|key| *key == name || key.starts_with(&prefix))
+        .cloned()
+        .partition(|key| key == name);
+    let scoped = scoped
+        .into_iter()
+        .filter_map(|key| {
+            let (file, offset) = key[prefix.len()..].rsplit_once(':')?;
+            let site = (PathBuf::from(file), offset.parse::<usize>().ok()?);
+            Some((key, site))
+        })
+        .collect::<Vec<_>>();
+
+    let nested = get_nested_site().and_then(|(file, offset)| {
+        scoped
+            .iter()
+            .filter(|(_, site)| site.0 == file && is_in_scope(&file, site.1, offset))
+            .max_by_key(|(_, site)| site.1)
+    });
+
+    match (nested, crate_level.first()) {
+        (Some((key, _)), _) => key.clone(),
+        (None, Some(key)) => key.clone(),
+        (None, None) => scoped
+            .iter()
+            .max_by_key(|(_, site)| site.clone())
+            .map(|(key, _)| key.clone())
+            .unwrap_or(name.to_string()),
+    }
 }
 
-fn set_lifetime_coords(name: String, digrphs: &Vec<RDigrph>) {
+fn set_lifetime_coords(name: String, digrphs: &[RDigrph]) {
+    let name = get_registry_key(&name);
     let mut lifetime_coords_map = LIFETIME_COORDS_MAP.lock().unwrap();
 
     let coords = concat(digrphs.iter().map(|digrph| digrph.get_coords()));
 
     //println!("[{}] set lifetime coords: {:?}", name, coords);
-    lifetime_coords_map.insert(name, coords);
+    lifetime_coords_map.insert(name.clone(), coords);
+
+    // the same coords by their structured paths
+    let mut lifetime_paths_map = LIFETIME_PATHS_MAP.lock().unwrap();
+    let paths = concat(digrphs.iter().map(|digrph| digrph.get_paths()));
+    lifetime_paths_map.insert(name, paths);
+}
+
+fn get_lifetime_paths(name: String) -> Vec<(String, u8)> {
+    let name = get_resolved_key(&name);
+    let lifetime_paths_map = LIFETIME_PATHS_MAP.lock().unwrap();
+
+    lifetime_paths_map.get(&name).cloned().unwrap_or(vec![])
+}
+
+fn set_lifetime_edges(name: String, edges: Vec<Edge>) {
+    let name = get_registry_key(&name);
+    let mut lifetime_edges_map = LIFETIME_EDGES_MAP.lock().unwrap();
+
+    lifetime_edges_map.insert(name, edges);
+}
+
+fn get_lifetime_edges(name: String) -> Vec<Edge> {
+    let name = get_resolved_key(&name);
+    let lifetime_edges_map = LIFETIME_EDGES_MAP.lock().unwrap();
+
+    lifetime_edges_map.get(&name).cloned().unwrap_or(vec![])
+}
+
+fn set_lifetime_params(name: String, params: Vec<bool>) {
+    let name = get_registry_key(&name);
+    let mut lifetime_params_map = LIFETIME_PARAMS_MAP.lock().unwrap();
+
+    lifetime_params_map.insert(name, params);
+}
+
+fn get_lifetime_params(name: String) -> Option<Vec<bool>> {
+    let name = get_resolved_key(&name);
+    let lifetime_params_map = LIFETIME_PARAMS_MAP.lock().unwrap();
+
+    lifetime_params_map.get(&name).cloned()
+}
+
+fn has_lifetime_coords(name: &str) -> bool {
+    let name = get_resolved_key(name);
+    let lifetime_coords_map = LIFETIME_COORDS_MAP.lock().unwrap();
+
+    lifetime_coords_map.contains_key(&name)
 }
 
 fn get_lifetime_coords(name: String) -> Vec<(String, u8)> {
+    let name = get_resolved_key(&name);
     let lifetime_coords_map = LIFETIME_COORDS_MAP.lock().unwrap();
     let lifetime_coords = lifetime_coords_map
         .get(&name.clone())
@@ -503,9 +2130,38 @@ fn set_lifetime_symbols(
     generics: Option<&mut Generics>,
     digrphs: &mut Vec<RDigrph>,
     symbol_generator: &mut SymbolGenerator,
-) {
+) -> Vec<(String, *mut Option<BoundLifetimes>)> {
     symbol_generator.take_a_snapshot();
 
+    // hand-written lifetime arguments of a registered type are its user lifetimes, they stay
+    for digrph in digrphs.iter_mut() {
+        let mut kept = vec![];
+        for node in digrph.nodes.iter() {
+            if let RNode::Segment(node) = node {
+                if !has_lifetime_coords(&node.name) {
+                    continue;
+                }
+                if let PathArguments::AngleBracketed(AngleBracketedGenericArguments {
+                    ref args,
+                    ..
+                }) = unsafe { &(*node.segment).arguments }
+                {
+                    kept.extend(args.iter().filter_map(|arg| match arg {
+                        GenericArgument::Lifetime(lt) => Some(lt as *const Lifetime),
+                        _ => None,
+                    }));
+                }
+            }
+        }
+        digrph.nodes.retain(|node| match node {
+            RNode::Lifetime(node) => !kept.contains(&(node.lifetime as *const Lifetime)),
+            _ => true,
+        });
+    }
+
+    // symbols of higher-ranked lifetimes, with their `for<..>`
+    let mut scoped = vec![];
+    let mut insertions = vec![];
     for digrph in digrphs.iter_mut() {
         for node in digrph.nodes.iter_mut() {
             match node {
@@ -514,53 +2170,147 @@ fn set_lifetime_symbols(
 
                     // refercence lifetime
                     (*node.lifetime).ident = Ident::new(&symbol[1..], Span::call_site());
+
+                    if let Some(scope) = node.scope {
+                        scoped.push((symbol, scope));
+                    }
                 },
                 RNode::Segment(node) => unsafe {
                     let name = (*node.segment).ident.to_string();
-                    let coords = get_lifetime_coords(name);
-
-                    for _ in coords.iter() {
-                        let symbol = symbol_generator.generate();
-
-                        // arguments lifetime
-                        if let PathArguments::None = (*node.segment).arguments {
-                            (*node.segment).arguments =
-                                PathArguments::AngleBracketed(AngleBracketedGenericArguments {
-                                    colon2_token: None,
-                                    lt_token: token::Lt {
-                                        spans: [Span::call_site(); 1],
-                                    },
-                                    args: punctuated::Punctuated::new(),
-                                    gt_token: token::Gt {
-                                        spans: [Span::call_site(); 1],
-                                    },
-                                })
-                        }
-                        if let PathArguments::AngleBracketed(AngleBracketedGenericArguments {
-                            ref mut args,
-                            ..
-                        }) = (*node.segment).arguments
-                        {
-                            let lt = Lifetime::new(&symbol, Span::call_site());
-                            args.push(GenericArgument::Lifetime(lt));
-                        }
+                    let coords = get_lifetime_coords(name.clone());
+
+                    let symbols = coords
+                        .iter()
+                        .map(|_| symbol_generator.generate())
+                        .collect::<Vec<_>>();
+                    if let Some(scope) = node.scope {
+                        scoped.extend(symbols.iter().map(|symbol| (symbol.clone(), scope)));
                     }
 
+                    // the symbols of merged coords are only generics, the carried edges
+                    // merge them again into the symbol of their param
+                    let params = get_lifetime_params(name.clone())
+                        .unwrap_or_else(|| vec![true; symbols.len()]);
+                    insertions.push((
+                        node.segment,
+                        symbols
+                            .iter()
+                            .zip(params)
+                            .filter(|(_, param)| *param)
+                            .map(|(symbol, _)| symbol.clone())
+                            .collect::<Vec<_>>(),
+                    ));
+
                     node.coords = Some(coords);
+                    node.paths = Some(get_lifetime_paths(name.clone()));
+                    node.edges = Some(get_lifetime_edges(name));
                 },
             }
         }
     }
 
+    // every symbol of a scope goes into its `for<..>` while the scope is where the node saw it,
+    // `LifetimeRenamer` takes out the ones merged, elided or not higher-ranked after all
+    if generics.is_some() {
+        for (symbol, scope) in scoped.iter() {
+            let lf_def = LifetimeDef::new(Lifetime::new(symbol, Span::call_site()));
+
+            unsafe {
+                match **scope {
+                    Some(ref mut bound_lifetimes) => bound_lifetimes.lifetimes.push(lf_def),
+                    None => {
+                        **scope = Some(BoundLifetimes {
+                            for_token: Default::default(),
+                            lt_token: Default::default(),
+                            lifetimes: std::iter::once(lf_def).collect(),
+                            gt_token: Default::default(),
+                        })
+                    }
+                }
+            }
+        }
+    }
+
+    // arguments lifetime, pushing arguments moves the ones behind the nodes,
+    // so they are pushed after all lifetimes are set, inner segments first
+    for (segment, symbols) in insertions.into_iter().rev() {
+        unsafe {
+            for symbol in symbols {
+                if let PathArguments::None = (*segment).arguments {
+                    (*segment).arguments =
+                        PathArguments::AngleBracketed(AngleBracketedGenericArguments {
+                            colon2_token: None,
+                            lt_token: token::Lt {
+                                spans: [Span::call_site(); 1],
+                            },
+                            args: punctuated::Punctuated::new(),
+                            gt_token: token::Gt {
+                                spans: [Span::call_site(); 1],
+                            },
+                        })
+                }
+                if let PathArguments::AngleBracketed(AngleBracketedGenericArguments {
+                    ref mut args,
+                    ..
+                }) = (*segment).arguments
+                {
+                    // after the lifetime arguments, before type and const arguments
+                    let lt = Lifetime::new(&symbol, Span::call_site());
+                    let index = args
+                        .iter()
+                        .take_while(|arg| matches!(arg, GenericArgument::Lifetime(_)))
+                        .count();
+                    args.insert(index, GenericArgument::Lifetime(lt));
+                }
+            }
+        }
+    }
+
     // generics lifetime
     if let Some(generics) = generics {
         for symbol in symbol_generator.regenerate() {
+            // after the lifetime params, before type and const params
             let lt = LifetimeDef::new(Lifetime::new(symbol.as_str(), Span::call_site()));
-            generics.params.push(GenericParam::from(lt));
+            let index = generics.lifetimes().count();
+            generics.params.insert(index, GenericParam::from(lt));
         }
     } else {
         symbol_generator.rollback();
     }
+
+    scoped
+}
+
+// Abbreviations of a coord cell: `x[Name,0]` is also `x`,
+// `generics/U[Trait,0]` is also `U[Trait,0]` and `U`.
+fn get_cell_aliases(cell: &str) -> Vec<String> {
+    let bracket_re = Regex::new(r"\[[^\[\]]+\]").unwrap();
+    let generics_re = Regex::new(r"(^|/)generics/").unwrap();
+
+    let mut aliases = vec![];
+    for alias in [
+        bracket_re.replace(cell, "").to_string(),
+        generics_re.replace(cell, "$1").to_string(),
+        generics_re
+            .replace(&bracket_re.replace(cell, ""), "$1")
+            .to_string(),
+    ] {
+        if alias != cell && !aliases.contains(&alias) {
+            aliases.push(alias);
+        }
+    }
+
+    aliases
+}
+
+// Whether the coord lies beneath the (abbreviated) prefix.
+fn is_coord_beneath(coord: &str, prefix: &[&str]) -> bool {
+    let cells = coord.trim_end_matches("$").split(".").collect::<Vec<_>>();
+
+    cells.len() >= prefix.len()
+        && cells.iter().zip(prefix.iter()).all(|(cell, prefix)| {
+            cell == prefix || get_cell_aliases(cell).iter().any(|alias| alias == prefix)
+        })
 }
 
 fn get_abbr_names_trie(coords: Vec<(String, u8)>) -> Trie<String, String> {
@@ -576,17 +2326,9 @@ fn get_abbr_names_trie(coords: Vec<(String, u8)>) -> Trie<String, String> {
 
         trie.insert(&path, name);
 
-        let re = Regex::new(r"\[[^\[\]]+\]").unwrap();
         let path_aliases = path
             .iter()
-            .map(|cell1| {
-                let cell2 = re.replace(cell1, "").to_string();
-                if cell1.len() == cell2.len() {
-                    vec![]
-                } else {
-                    vec![cell2]
-                }
-            })
+            .map(|cell| get_cell_aliases(cell))
             .collect::<Vec<_>>();
 
         let path = &path.iter().map(|s| s).collect::<Vec<_>>()[..];
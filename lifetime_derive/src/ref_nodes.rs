@@ -1,32 +1,55 @@
-use proc_macro2::Span;
+use crate::{has_lifetime_coords, is_type_param};
+use proc_macro2::{Group, Span, TokenTree};
 use quote::quote;
 use regex::Regex;
 use std::collections::HashMap;
 use syn::punctuated::Punctuated;
 use syn::*;
 
+// `(name1, index1, name2, index2)`, the lifetime of the first coord outlives the second one
+pub type Edge = (String, u8, String, u8);
+
 #[derive(Debug)]
 pub struct LifetimeNode {
     pub lifetime: *mut Lifetime,
+    pub path: String,
+    // the `for<..>` of the innermost higher-ranked bound or bare fn around the node, valid
+    // until arguments are pushed, an id after that
+    pub scope: Option<*mut Option<BoundLifetimes>>,
 }
 
 impl<'a> LifetimeNode {
     fn new(lifetime: *mut Lifetime) -> Self {
-        Self { lifetime: lifetime }
+        Self {
+            lifetime,
+            path: String::new(),
+            scope: None,
+        }
     }
 }
 
 #[derive(Debug)]
 pub struct SegmentNode {
     pub segment: *mut PathSegment,
+    // read once, the segment may move when arguments are pushed in front of it
+    pub name: String,
     pub coords: Option<Vec<(String, u8)>>,
+    pub paths: Option<Vec<(String, u8)>>,
+    pub edges: Option<Vec<Edge>>,
+    pub path: String,
+    pub scope: Option<*mut Option<BoundLifetimes>>,
 }
 
 impl SegmentNode {
     fn new(segment: *mut PathSegment) -> Self {
         Self {
-            segment: segment,
+            segment,
+            name: unsafe { (*segment).ident.to_string() },
             coords: None,
+            paths: None,
+            edges: None,
+            path: String::new(),
+            scope: None,
         }
     }
 }
@@ -45,12 +68,56 @@ impl RNode {
     fn new_segment(segment: *mut PathSegment) -> Self {
         Self::Segment(SegmentNode::new(segment))
     }
+
+    // the capture bound `+ 'x` pushed on an `impl Trait` of the output, its path is `+`
+    fn new_capture(lifetime: *mut Lifetime) -> Self {
+        Self::new_lifetime(lifetime).prefix_path("+")
+    }
+
+    // structured path of the node inside its digrph, e.g. `.1`, `.Some`, `.Ok.0`
+    fn prefix_path(self, step: &str) -> Self {
+        self.prefix_raw_path(&format!(".{}", step))
+    }
+
+    fn prefix_raw_path(mut self, prefix: &str) -> Self {
+        match self {
+            RNode::Lifetime(ref mut node) => node.path = format!("{}{}", prefix, node.path),
+            RNode::Segment(ref mut node) => node.path = format!("{}{}", prefix, node.path),
+        }
+
+        self
+    }
+
+    fn set_scope(mut self, scope: *mut Option<BoundLifetimes>) -> Self {
+        match self {
+            RNode::Lifetime(ref mut node) => node.scope = node.scope.or(Some(scope)),
+            RNode::Segment(ref mut node) => node.scope = node.scope.or(Some(scope)),
+        }
+
+        self
+    }
+}
+
+fn set_scopes(nodes: Vec<RNode>, scope: *mut Option<BoundLifetimes>) -> Vec<RNode> {
+    nodes
+        .into_iter()
+        .map(|node| node.set_scope(scope))
+        .collect()
+}
+
+fn prefix_paths(nodes: Vec<RNode>, step: &str) -> Vec<RNode> {
+    nodes
+        .into_iter()
+        .map(|node| node.prefix_path(step))
+        .collect()
 }
 
 #[derive(Debug)]
 pub struct RDigrph {
     pub name: String,
     pub nodes: Vec<RNode>,
+    // field or variant options, `("static", "")` or `("name", "src")`
+    pub options: Vec<(String, String)>,
 }
 
 impl RDigrph {
@@ -58,6 +125,7 @@ impl RDigrph {
         Self {
             name: name,
             nodes: vec![],
+            options: vec![],
         }
     }
 
@@ -78,11 +146,10 @@ impl RDigrph {
 
                 // segment coords
                 RNode::Segment(SegmentNode {
-                    segment,
+                    name,
                     coords: Some(cds),
                     ..
-                }) => unsafe {
-                    let name = (**segment).ident.to_string();
+                }) => {
                     let index = index_counters.entry(name.clone()).or_insert(-1);
                     *index += 1;
                     let re = Regex::new(r"^[a-zA-Z_][a-zA-Z0-9_]*/").unwrap();
@@ -108,14 +175,128 @@ impl RDigrph {
 
         coords
     }
+
+    // Edges declared on the registered types, their coords spliced like `get_coords` does.
+    pub fn get_carried_edges(&self) -> Vec<Edge> {
+        let mut edges = vec![];
+        let mut index_counters = HashMap::new();
+        let re = Regex::new(r"^[a-zA-Z_][a-zA-Z0-9_]*/").unwrap();
+
+        for node in self.nodes.iter() {
+            if let RNode::Segment(SegmentNode {
+                name,
+                coords: Some(_),
+                edges: Some(eds),
+                ..
+            }) = node
+            {
+                let index = index_counters.entry(name.clone()).or_insert(-1);
+                *index += 1;
+
+                let splice = |cd: &String| {
+                    // 'static stays as it is
+                    if cd.starts_with("'") {
+                        cd.clone()
+                    } else {
+                        format!(
+                            "{}{}",
+                            self.name,
+                            re.replace(cd.as_str(), format!("[{},{}].", name, index).as_str())
+                        )
+                    }
+                };
+                edges.extend(eds.iter().map(|(name1, index1, name2, index2)| {
+                    (splice(name1), *index1, splice(name2), *index2)
+                }));
+            }
+        }
+
+        edges
+    }
+
+    // Every lifetime of an `impl Trait` outlives its capture bound `+`.
+    pub fn get_capture_edges(&self) -> Vec<Edge> {
+        let paths = self.get_paths();
+
+        let mut edges = vec![];
+        for (capture, index) in paths.iter().filter(|(path, _)| path.ends_with(".+$")) {
+            let prefix = capture.trim_end_matches(".+$");
+
+            for (path, path_index) in paths.iter() {
+                if path != capture
+                    && path.starts_with(prefix)
+                    && path[prefix.len()..].starts_with(&['.', '[', '$'][..])
+                {
+                    edges.push((path.clone(), *path_index, capture.clone(), *index));
+                }
+            }
+        }
+
+        edges
+    }
+
+    // Same order as `get_coords`, but every coord is named by its structured path
+    // (`Output!.1`, `Output!.Some[Demo6A,0].0`) instead of a flat index.
+    pub fn get_paths(&self) -> Vec<(String, u8)> {
+        let mut paths = vec![];
+        let mut index_counters = HashMap::new();
+        let re = Regex::new(r"^[a-zA-Z_][a-zA-Z0-9_]*/").unwrap();
+
+        for node in self.nodes.iter() {
+            match node {
+                // lifetime paths
+                RNode::Lifetime(LifetimeNode { path, .. }) => {
+                    let index = index_counters.entry(path.clone()).or_insert(-1);
+                    *index += 1;
+
+                    // $ is end
+                    paths.push((format!("{}{}$", self.name, path), *index as u8));
+                }
+
+                // segment paths
+                RNode::Segment(SegmentNode {
+                    name,
+                    paths: Some(pts),
+                    path,
+                    ..
+                }) => {
+                    let index = index_counters
+                        .entry(format!("{}[{}]", path, name))
+                        .or_insert(-1);
+                    *index += 1;
+
+                    paths.extend(pts.iter().map(|pt| {
+                        (
+                            format!(
+                                "{}{}{}",
+                                self.name,
+                                path,
+                                re.replace(
+                                    pt.0.as_str(),
+                                    format!("[{},{}].", name.clone(), index).as_str()
+                                )
+                            ),
+                            pt.1,
+                        )
+                    }));
+                }
+                _ => (),
+            }
+        }
+
+        paths
+    }
 }
 
 pub enum ROrigin<'a> {
     FnInputs(&'a mut Punctuated<FnArg, token::Comma>),
     FnOutput(&'a mut ReturnType),
+    // the future of an async fn captures every input lifetime, its output needs no capture bound
+    AsyncFnOutput(&'a mut ReturnType),
     StructFields(&'a mut Fields),
     EnumVariants(&'a mut Punctuated<Variant, token::Comma>),
     SelfTY(&'a mut Box<Type>),
+    ItemTY(&'a mut Box<Type>),
     Trait(&'a mut Option<(Option<Token![!]>, Path, Token![for])>),
     Generics(&'a mut Generics),
 }
@@ -124,6 +305,7 @@ pub fn get_ref_digrphs<'a>(namespace: String, origins: Vec<ROrigin<'a>>) -> Vec<
     let mut digrphs = vec![];
 
     for origin in origins {
+        let capture = matches!(origin, ROrigin::FnOutput(_));
         match origin {
             ROrigin::FnInputs(inputs) => {
                 for input in inputs.iter_mut() {
@@ -142,8 +324,11 @@ pub fn get_ref_digrphs<'a>(namespace: String, origins: Vec<ROrigin<'a>>) -> Vec<
                                 .push(RNode::new_lifetime(olf.as_mut().unwrap()));
                         }
                         FnArg::Typed(pt) => {
-                            digrph.name = format_digrph_name(namespace.clone(), get_name_from_pat(&pt.pat));
-                            digrph.nodes.extend(get_ref_nodes_from_type(&mut *pt.ty));
+                            digrph.name =
+                                format_digrph_name(namespace.clone(), get_name_from_pat(&pt.pat));
+                            digrph
+                                .nodes
+                                .extend(get_ref_nodes_from_type(&mut *pt.ty, false));
                         }
                         _ => (),
                     }
@@ -151,12 +336,12 @@ pub fn get_ref_digrphs<'a>(namespace: String, origins: Vec<ROrigin<'a>>) -> Vec<
                     digrphs.push(digrph);
                 }
             }
-            ROrigin::FnOutput(output) => {
+            ROrigin::FnOutput(output) | ROrigin::AsyncFnOutput(output) => {
                 let mut digrph = RDigrph::new(format_digrph_name(namespace.clone(), "Output!".to_string()));
 
                 match output {
                     ReturnType::Type(_, box ty) => {
-                        digrph.nodes.extend(get_ref_nodes_from_type(ty));
+                        digrph.nodes.extend(get_ref_nodes_from_type(ty, capture));
                     }
                     _ => (),
                 }
@@ -170,8 +355,14 @@ pub fn get_ref_digrphs<'a>(namespace: String, origins: Vec<ROrigin<'a>>) -> Vec<
                         .as_ref()
                         .map_or(i.to_string(), |ident| ident.to_string());
                     let mut digrph = RDigrph::new(format_digrph_name(namespace.clone(), field_name));
+                    digrph.options = take_lifetime_options(&mut field.attrs);
 
-                    digrph.nodes.extend(get_ref_nodes_from_type(&mut field.ty));
+                    // a skipped field keeps its hand-written lifetimes
+                    if !digrph.options.iter().any(|(key, _)| key == "skip") {
+                        digrph
+                            .nodes
+                            .extend(get_ref_nodes_from_type(&mut field.ty, false));
+                    }
 
                     digrphs.push(digrph);
                 }
@@ -180,9 +371,20 @@ pub fn get_ref_digrphs<'a>(namespace: String, origins: Vec<ROrigin<'a>>) -> Vec<
                 for variant in variants.iter_mut() {
                     let mut digrph =
                         RDigrph::new(format_digrph_name(namespace.clone(), variant.ident.to_string()));
-
-                    for field in variant.fields.iter_mut() {
-                        digrph.nodes.extend(get_ref_nodes_from_type(&mut field.ty));
+                    digrph.options = take_lifetime_options(&mut variant.attrs);
+
+                    // the fields are also named like struct fields, `Ident.text` or `Double.0`
+                    if !digrph.options.iter().any(|(key, _)| key == "skip") {
+                        for (i, field) in variant.fields.iter_mut().enumerate() {
+                            let field_name = field
+                                .ident
+                                .as_ref()
+                                .map_or(i.to_string(), |ident| ident.to_string());
+                            digrph.nodes.extend(prefix_paths(
+                                get_ref_nodes_from_type(&mut field.ty, false),
+                                &field_name,
+                            ));
+                        }
                     }
 
                     digrphs.push(digrph);
@@ -193,7 +395,17 @@ pub fn get_ref_digrphs<'a>(namespace: String, origins: Vec<ROrigin<'a>>) -> Vec<
 
                 digrph
                     .nodes
-                    .extend(get_ref_nodes_from_type(self_ty.as_mut()));
+                    .extend(get_ref_nodes_from_type(self_ty.as_mut(), false));
+
+                digrphs.push(digrph);
+            }
+            ROrigin::ItemTY(item_ty) => {
+                let mut digrph =
+                    RDigrph::new(format_digrph_name(namespace.clone(), "type".to_string()));
+
+                digrph
+                    .nodes
+                    .extend(get_ref_nodes_from_type(item_ty.as_mut(), false));
 
                 digrphs.push(digrph);
             }
@@ -201,7 +413,7 @@ pub fn get_ref_digrphs<'a>(namespace: String, origins: Vec<ROrigin<'a>>) -> Vec<
                 let mut digrph = RDigrph::new(format_digrph_name(namespace.clone(), "trait".to_string()));
 
                 match trait_ {
-                    Some((_, path, _)) => digrph.nodes.extend(get_ref_nodes_from_path(path)),
+                    Some((_, path, _)) => digrph.nodes.extend(get_ref_nodes_from_path(path, false)),
                     None => (),
                 }
 
@@ -216,16 +428,22 @@ pub fn get_ref_digrphs<'a>(namespace: String, origins: Vec<ROrigin<'a>>) -> Vec<
 
                             for tpb in tp.bounds.iter_mut() {
                                 if let TypeParamBound::Trait(tb) = tpb {
-                                    digrph.nodes.extend(get_ref_nodes_from_path(&mut tb.path))
+                                    digrph
+                                        .nodes
+                                        .extend(get_ref_nodes_from_path(&mut tb.path, false))
                                 }
                             }
 
                             digrphs.push(digrph);
                         }
                         GenericParam::Const(cp) => {
-                            let mut digrph =
-                                RDigrph::new(format_digrph_name(namespace.clone(), cp.ident.to_string()));
-                            digrph.nodes.extend(get_ref_nodes_from_type(&mut cp.ty));
+                            let mut digrph = RDigrph::new(format_digrph_name(
+                                namespace.clone(),
+                                cp.ident.to_string(),
+                            ));
+                            digrph
+                                .nodes
+                                .extend(get_ref_nodes_from_type(&mut cp.ty, false));
 
                             digrphs.push(digrph);
                         }
@@ -250,7 +468,9 @@ pub fn get_ref_digrphs<'a>(namespace: String, origins: Vec<ROrigin<'a>>) -> Vec<
 
                                 for tpb in bounds.iter_mut() {
                                     if let TypeParamBound::Trait(tb) = tpb {
-                                        digrph.nodes.extend(get_ref_nodes_from_path(&mut tb.path))
+                                        digrph
+                                            .nodes
+                                            .extend(get_ref_nodes_from_path(&mut tb.path, false))
                                     }
                                 }
 
@@ -260,7 +480,9 @@ pub fn get_ref_digrphs<'a>(namespace: String, origins: Vec<ROrigin<'a>>) -> Vec<
                                 let mut digrph =
                                     RDigrph::new(format_digrph_name(namespace.clone(), i.to_string()));
 
-                                digrph.nodes.extend(get_ref_nodes_from_type(bounded_ty));
+                                digrph
+                                    .nodes
+                                    .extend(get_ref_nodes_from_type(bounded_ty, false));
 
                                 digrphs.push(digrph);
                             }
@@ -276,7 +498,7 @@ pub fn get_ref_digrphs<'a>(namespace: String, origins: Vec<ROrigin<'a>>) -> Vec<
     digrphs
 }
 
-fn get_ref_nodes_from_type<'a>(ty: &'a mut Type) -> Vec<RNode> {
+fn get_ref_nodes_from_type<'a>(ty: &'a mut Type, capture: bool) -> Vec<RNode> {
     //println!("ty: {:#?}", ty);
     let mut nodes = vec![];
 
@@ -284,62 +506,154 @@ fn get_ref_nodes_from_type<'a>(ty: &'a mut Type) -> Vec<RNode> {
         Type::Reference(tr) => {
             tr.lifetime = Some(Lifetime::new("'null", Span::call_site()));
 
+            // `&(impl Trait + 'x)`, the capture bound needs the parentheses
+            if capture && matches!(*tr.elem, Type::ImplTrait(_)) {
+                let elem = std::mem::replace(&mut *tr.elem, Type::Verbatim(quote!()));
+                *tr.elem = Type::Paren(TypeParen {
+                    paren_token: token::Paren::default(),
+                    elem: Box::new(elem),
+                });
+            }
+
             nodes.push(RNode::new_lifetime(tr.lifetime.as_mut().unwrap()));
-            nodes.extend(get_ref_nodes_from_type(&mut *tr.elem));
+            nodes.extend(get_ref_nodes_from_type(&mut *tr.elem, capture));
+        }
+        Type::Slice(TypeSlice { elem, .. }) | Type::Array(TypeArray { elem, .. }) => {
+            nodes.extend(get_ref_nodes_from_type(&mut *elem, capture));
         }
         Type::Tuple(tt) => {
-            for elem in tt.elems.iter_mut() {
-                nodes.extend(get_ref_nodes_from_type(elem));
+            for (i, elem) in tt.elems.iter_mut().enumerate() {
+                nodes.extend(prefix_paths(
+                    get_ref_nodes_from_type(elem, capture),
+                    &i.to_string(),
+                ));
             }
         }
         Type::Path(TypePath { qself, path, .. }) => {
-            if let Some(qself) = qself {
-                nodes.extend(get_ref_nodes_from_type(&mut *qself.ty));
+            // `<T as Trait>::Assoc`, `T::Assoc` or `Self::Assoc`, the segments after the trait,
+            // the type param or the user type are associated items, never user types
+            let position = match qself {
+                Some(qself) => {
+                    nodes.extend(get_ref_nodes_from_type(&mut qself.ty, capture));
+                    qself.position
+                }
+                None if path.segments.len() > 1
+                    && is_type_param(&path.segments[0].ident.to_string()) =>
+                {
+                    1
+                }
+                None => path
+                    .segments
+                    .iter()
+                    .position(|segment| has_lifetime_coords(&segment.ident.to_string()))
+                    .map_or(path.segments.len(), |i| i + 1),
+            };
+            let trait_ = match qself {
+                Some(_) if position > 0 => Some(path.segments[position - 1].ident.to_string()),
+                _ => None,
+            };
+
+            for (i, segment) in path.segments.iter_mut().enumerate() {
+                if i < position {
+                    nodes.push(RNode::new_segment(segment));
+                    nodes.extend(get_ref_nodes_from_arguments(segment, capture));
+                } else {
+                    // `x[Trait].Assoc` or `x.Assoc`
+                    let prefix = match &trait_ {
+                        Some(trait_) => format!("[{},0].{}", trait_, segment.ident),
+                        None => format!(".{}", segment.ident),
+                    };
+                    nodes.extend(
+                        get_ref_nodes_from_arguments(segment, capture)
+                            .into_iter()
+                            .map(|node| node.prefix_raw_path(&prefix)),
+                    );
+                }
             }
-
-            nodes.extend(get_ref_nodes_from_path(path));
         }
         Type::BareFn(bf) => {
             //println!("BareFn: {:#?}", bf);
 
             // bare fn inputs
-            for input in bf.inputs.iter_mut() {
-                nodes.extend(get_ref_nodes_from_type(&mut input.ty));
+            let mut fn_nodes = vec![];
+            for (i, input) in bf.inputs.iter_mut().enumerate() {
+                fn_nodes.extend(prefix_paths(
+                    get_ref_nodes_from_type(&mut input.ty, capture),
+                    &i.to_string(),
+                ));
             }
 
             //bare fn output
             match &mut bf.output {
                 ReturnType::Type(_, box ty) => {
-                    nodes.extend(get_ref_nodes_from_type(ty));
+                    fn_nodes.extend(prefix_paths(
+                        get_ref_nodes_from_type(ty, capture),
+                        "Output!",
+                    ));
                 }
                 _ => (),
             }
+
+            // a bare fn is its own higher-ranked scope
+            nodes.extend(set_scopes(fn_nodes, &mut bf.lifetimes));
         }
-        Type::ImplTrait(it) => {
-            //println!("ImplTrait: {:#?}", it);
+        Type::ImplTrait(TypeImplTrait { bounds, .. }) => {
+            // in the output it captures what its capture bound outlives, unless it has a
+            // lifetime bound of its own
+            let capture = capture
+                && !bounds
+                    .iter()
+                    .any(|bound| matches!(bound, TypeParamBound::Lifetime(_)));
+            if capture {
+                bounds.push(TypeParamBound::Lifetime(Lifetime::new(
+                    "'_",
+                    Span::call_site(),
+                )));
+            }
 
-            for bound in it.bounds.iter_mut() {
-                match bound {
-                    TypeParamBound::Trait(tb) => {
-                        nodes.extend(get_ref_nodes_from_path(&mut tb.path));
+            nodes.extend(get_ref_nodes_from_bounds(bounds, capture));
+            if let (true, Some(TypeParamBound::Lifetime(lf))) = (capture, bounds.last_mut()) {
+                nodes.push(RNode::new_capture(lf));
+            }
+        }
+        Type::TraitObject(TypeTraitObject { bounds, .. }) => {
+            nodes.extend(get_ref_nodes_from_bounds(bounds, capture));
+        }
+        // `$t:ty` fragments of `macro_rules!` arrive as invisible groups
+        Type::Paren(TypeParen { elem, .. }) | Type::Group(TypeGroup { elem, .. }) => {
+            nodes.extend(get_ref_nodes_from_type(&mut *elem, capture));
+        }
+        Type::Macro(TypeMacro { mac, .. }) => {
+            // opaque, unless its lifetimes are declared by hand as `'_`, e.g. `str_ref!('_)`;
+            // they ride on the macro path as `str_ref::<'_>!`, which can't be written by hand,
+            // until `LifetimeRenamer` puts them back
+            let count = count_macro_lifetimes(mac.tokens.clone());
+            if count > 0 {
+                let segment = mac.path.segments.last_mut().unwrap();
+                segment.arguments = PathArguments::AngleBracketed(AngleBracketedGenericArguments {
+                    colon2_token: Some(token::Colon2::default()),
+                    lt_token: token::Lt::default(),
+                    args: (0..count)
+                        .map(|_| GenericArgument::Lifetime(Lifetime::new("'_", Span::call_site())))
+                        .collect(),
+                    gt_token: token::Gt::default(),
+                });
+
+                if let PathArguments::AngleBracketed(ab) = &mut segment.arguments {
+                    for arg in ab.args.iter_mut() {
+                        if let GenericArgument::Lifetime(lt) = arg {
+                            nodes.push(RNode::new_lifetime(lt));
+                        }
                     }
-                    TypeParamBound::Lifetime(_) => (),
                 }
             }
         }
-        Type::Infer(_) => (),
+        Type::Never(_) | Type::Infer(_) => (),
         _ => {
             println!("ty: {:#?}", ty);
             unreachable!()
         } /*
-          Type::Array(_) => {}
-          Type::Group(_) => {}
-          Type::Macro(_) => {}
-          Type::Never(_) => {}
-          Type::Paren(_) => {}
           Type::Ptr(_) => {}
-          Type::Slice(_) => {}
-          Type::TraitObject(_) => {}
           Type::Verbatim(_) => {}
           Type::__Nonexhaustive => {}
           */
@@ -348,61 +662,173 @@ fn get_ref_nodes_from_type<'a>(ty: &'a mut Type) -> Vec<RNode> {
     nodes
 }
 
-fn get_ref_nodes_from_path<'a>(path: &'a mut Path) -> Vec<RNode> {
+// The `'_` lifetimes declared in a type macro, in order.
+fn count_macro_lifetimes(tokens: proc_macro2::TokenStream) -> usize {
+    let mut count = 0;
+    let mut apostrophe = false;
+    for tt in tokens {
+        match tt {
+            TokenTree::Punct(punct) if punct.as_char() == '\'' => {
+                apostrophe = true;
+                continue;
+            }
+            TokenTree::Ident(ident) if apostrophe && ident == "_" => count += 1,
+            TokenTree::Group(group) => count += count_macro_lifetimes(group.stream()),
+            _ => (),
+        }
+        apostrophe = false;
+    }
+
+    count
+}
+
+// Puts the lifetimes of a type macro back in place of its `'_`.
+pub fn set_macro_lifetimes(
+    tokens: proc_macro2::TokenStream,
+    lifetimes: &mut impl Iterator<Item = Lifetime>,
+) -> proc_macro2::TokenStream {
+    let mut stream = vec![];
+    let mut iter = tokens.into_iter().peekable();
+    while let Some(tt) = iter.next() {
+        match tt {
+            TokenTree::Punct(ref punct) if punct.as_char() == '\'' => match iter.peek() {
+                Some(TokenTree::Ident(ident)) if ident == "_" => {
+                    iter.next();
+                    let lt = lifetimes.next().unwrap();
+                    stream.extend(quote!(#lt));
+                }
+                _ => stream.push(tt),
+            },
+            TokenTree::Group(group) => {
+                let mut new_group = Group::new(
+                    group.delimiter(),
+                    set_macro_lifetimes(group.stream(), lifetimes),
+                );
+                new_group.set_span(group.span());
+                stream.push(TokenTree::Group(new_group));
+            }
+            _ => stream.push(tt),
+        }
+    }
+
+    stream.into_iter().collect()
+}
+
+// The bounds of `impl Trait` and `dyn Trait`.
+// `Fn(&T) -> &T` bounds are their own higher-ranked scope.
+fn get_ref_nodes_from_bounds(
+    bounds: &mut Punctuated<TypeParamBound, Token![+]>,
+    capture: bool,
+) -> Vec<RNode> {
+    let mut nodes = vec![];
+
+    for bound in bounds.iter_mut() {
+        match bound {
+            TypeParamBound::Trait(tb) => {
+                let parenthesized =
+                    tb.path.segments.iter().any(|segment| {
+                        matches!(segment.arguments, PathArguments::Parenthesized(_))
+                    });
+
+                if parenthesized {
+                    let scope: *mut Option<BoundLifetimes> = &mut tb.lifetimes;
+                    nodes.extend(set_scopes(
+                        get_ref_nodes_from_path(&mut tb.path, capture),
+                        scope,
+                    ));
+                } else {
+                    nodes.extend(get_ref_nodes_from_path(&mut tb.path, capture));
+                }
+            }
+            // lifetime bounds, `'static` or named, stay as they are
+            TypeParamBound::Lifetime(_) => (),
+        }
+    }
+
+    nodes
+}
+
+fn get_ref_nodes_from_path<'a>(path: &'a mut Path, capture: bool) -> Vec<RNode> {
     let mut nodes = vec![];
 
     for segment in path.segments.iter_mut() {
         nodes.push(RNode::new_segment(segment));
+        nodes.extend(get_ref_nodes_from_arguments(segment, capture));
+    }
 
-        match segment.arguments {
-            PathArguments::AngleBracketed(AngleBracketedGenericArguments {
-                ref mut args, ..
-            }) => {
-                for arg in args {
-                    match arg {
-                        GenericArgument::Type(ref mut ty) => {
-                            nodes.extend(get_ref_nodes_from_type(ty));
-                        }
-                        GenericArgument::Binding(Binding { ref mut ty, .. }) => {
-                            nodes.extend(get_ref_nodes_from_type(ty));
-                        }
-                        GenericArgument::Constraint(Constraint { ref mut bounds, .. }) => {
-                            for bound in bounds {
-                                match bound {
-                                    TypeParamBound::Trait(TraitBound {
-                                        path: ref mut pt, ..
-                                    }) => {
-                                        nodes.extend(get_ref_nodes_from_path(pt));
-                                    }
-                                    _ => (),
-                                }
+    nodes
+}
+
+fn get_ref_nodes_from_arguments(segment: &mut PathSegment, capture: bool) -> Vec<RNode> {
+    let mut nodes = vec![];
+
+    // `Option` and `Result` arguments are named after their variants,
+    // other type arguments by their position.
+    let steps: Vec<String> = match segment.ident.to_string().as_str() {
+        "Option" => vec!["Some".to_string()],
+        "Result" => vec!["Ok".to_string(), "Err".to_string()],
+        _ => vec![],
+    };
+    let mut ty_index = 0;
+
+    match segment.arguments {
+        PathArguments::AngleBracketed(AngleBracketedGenericArguments { ref mut args, .. }) => {
+            for arg in args {
+                match arg {
+                    GenericArgument::Type(ref mut ty) => {
+                        let step = steps.get(ty_index).cloned().unwrap_or(ty_index.to_string());
+                        ty_index += 1;
+
+                        nodes.extend(prefix_paths(get_ref_nodes_from_type(ty, capture), &step));
+                    }
+                    GenericArgument::Binding(Binding {
+                        ref ident,
+                        ref mut ty,
+                        ..
+                    }) => {
+                        nodes.extend(prefix_paths(
+                            get_ref_nodes_from_type(ty, capture),
+                            &ident.to_string(),
+                        ));
+                    }
+                    GenericArgument::Constraint(Constraint { ref mut bounds, .. }) => {
+                        for bound in bounds {
+                            if let TypeParamBound::Trait(TraitBound {
+                                path: ref mut pt, ..
+                            }) = bound
+                            {
+                                nodes.extend(get_ref_nodes_from_path(pt, capture));
                             }
                         }
-                        GenericArgument::Lifetime(lf) => {
-                            nodes.push(RNode::new_lifetime(lf));
-                        }
-                        _ => (),
-                        /*
-                        GenericArgument::Const(_) => {}
-                        */
                     }
+                    GenericArgument::Lifetime(lf) => {
+                        nodes.push(RNode::new_lifetime(lf));
+                    }
+                    // `16` or `{ N + 1 }` stay where they are, they have no lifetimes
+                    GenericArgument::Const(_) => (),
                 }
             }
-            PathArguments::Parenthesized(ParenthesizedGenericArguments {
-                ref mut inputs,
-                ref mut output,
-                ..
-            }) => {
-                for input in inputs {
-                    nodes.extend(get_ref_nodes_from_type(input));
-                }
+        }
+        PathArguments::Parenthesized(ParenthesizedGenericArguments {
+            ref mut inputs,
+            ref mut output,
+            ..
+        }) => {
+            for (i, input) in inputs.iter_mut().enumerate() {
+                nodes.extend(prefix_paths(
+                    get_ref_nodes_from_type(input, capture),
+                    &i.to_string(),
+                ));
+            }
 
-                if let ReturnType::Type(_, box ref mut ty) = output {
-                    nodes.extend(get_ref_nodes_from_type(ty));
-                }
+            if let ReturnType::Type(_, box ref mut ty) = output {
+                nodes.extend(prefix_paths(
+                    get_ref_nodes_from_type(ty, capture),
+                    "Output!",
+                ));
             }
-            _ => (),
         }
+        _ => (),
     }
 
     nodes
@@ -418,7 +844,35 @@ fn get_name_from_pat(pat: &Pat) -> String {
     }
 }
 
-fn format_digrph_name(namespace: String, name: String) -> String {
+// `#[lifetime(static)]`, `#[lifetime(skip)]`, `#[lifetime(name = "src")]` and
+// `#[lifetime(share = "src")]` of a field or a variant, the attributes are removed.
+fn take_lifetime_options(attrs: &mut Vec<Attribute>) -> Vec<(String, String)> {
+    let re =
+        Regex::new(r#"^(static|skip)$|^(name|share)\s*=\s*"([a-zA-Z_][a-zA-Z0-9_]*)"$"#).unwrap();
+
+    let mut options = vec![];
+    for attr in attrs.iter() {
+        if attr.path.is_ident("lifetime") {
+            let group: proc_macro2::Group = syn::parse2(attr.tokens.clone()).unwrap();
+            let stream = group.stream().to_string();
+
+            for option in stream.split(",").filter(|option| !option.trim().is_empty()) {
+                match re.captures(option.trim()) {
+                    Some(caps) => match caps.get(1) {
+                        Some(key) => options.push((key.as_str().to_string(), String::new())),
+                        None => options.push((caps[2].to_string(), caps[3].to_string())),
+                    },
+                    None => panic!("unknown lifetime option: {}", option.trim()),
+                }
+            }
+        }
+    }
+    attrs.retain(|attr| !attr.path.is_ident("lifetime"));
+
+    options
+}
+
+pub fn format_digrph_name(namespace: String, name: String) -> String {
     if namespace.is_empty() {
         name
     } else {
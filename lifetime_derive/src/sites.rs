@@ -0,0 +1,138 @@
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+lazy_static! {
+    // the file and the offset of the attribute of the running expansion
+    static ref SITE: Mutex<Option<(PathBuf, usize)>> = Mutex::new(None);
+    // the brace depth at every byte of the files read so far
+    static ref DEPTHS_MAP: Mutex<HashMap<PathBuf, Vec<u16>>> = Mutex::new(HashMap::new());
+}
+
+pub fn set_site(span: proc_macro::Span) {
+    *SITE.lock().unwrap() = span
+        .local_file()
+        .map(|file| (file, span.byte_range().start));
+}
+
+// The site inside a block, e.g. the body of a fn, `None` at crate level or out of a file.
+pub fn get_nested_site() -> Option<(PathBuf, usize)> {
+    let site = SITE.lock().unwrap().clone();
+
+    site.filter(|(file, offset)| get_depth(file, *offset) > 0)
+}
+
+// An item at `from` is seen at `to` while the block around it is open.
+pub fn is_in_scope(file: &PathBuf, from: usize, to: usize) -> bool {
+    let mut depths_map = DEPTHS_MAP.lock().unwrap();
+    let depths = get_depths(&mut depths_map, file);
+
+    from <= to && to < depths.len() && depths[from..=to].iter().all(|depth| *depth >= depths[from])
+}
+
+fn get_depth(file: &PathBuf, offset: usize) -> u16 {
+    let mut depths_map = DEPTHS_MAP.lock().unwrap();
+    let depths = get_depths(&mut depths_map, file);
+
+    depths.get(offset).cloned().unwrap_or(0)
+}
+
+fn get_depths<'a>(depths_map: &'a mut HashMap<PathBuf, Vec<u16>>, file: &PathBuf) -> &'a Vec<u16> {
+    depths_map.entry(file.clone()).or_insert_with(|| {
+        std::fs::read_to_string(file)
+            .map(|text| get_brace_depths(&text))
+            .unwrap_or_default()
+    })
+}
+
+// Braces in comments, strings and chars don't count, a `'` without a closing one is a lifetime.
+fn get_brace_depths(text: &str) -> Vec<u16> {
+    let bytes = text.as_bytes();
+    let is_ident =
+        |i: usize| i > 0 && (bytes[i - 1].is_ascii_alphanumeric() || bytes[i - 1] == b'_');
+    let find = |from: usize, pattern: &str| {
+        text[from..]
+            .find(pattern)
+            .map(|i| from + i + pattern.len())
+            .unwrap_or(bytes.len())
+    };
+
+    let mut depths = vec![0; bytes.len()];
+    let mut depth: u16 = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let next = bytes.get(i + 1).cloned();
+        i = match bytes[i] {
+            b'/' if next == Some(b'/') => find(i, "\n"),
+            b'/' if next == Some(b'*') => {
+                let mut level = 0;
+                let mut j = i;
+                while j < bytes.len() {
+                    if bytes[j..].starts_with(b"/*") {
+                        level += 1;
+                        j += 2;
+                    } else if bytes[j..].starts_with(b"*/") {
+                        level -= 1;
+                        j += 2;
+                        if level == 0 {
+                            break;
+                        }
+                    } else {
+                        j += 1;
+                    }
+                }
+                j
+            }
+            // `r"..."` and `r#"..."#`, also after `b`
+            b'r' if matches!(next, Some(b'"') | Some(b'#'))
+                && (!is_ident(i) || (bytes[i - 1] == b'b' && !is_ident(i - 1))) =>
+            {
+                let hashes = bytes[i + 1..]
+                    .iter()
+                    .take_while(|byte| **byte == b'#')
+                    .count();
+                if bytes.get(i + 1 + hashes) == Some(&b'"') {
+                    find(i + 2 + hashes, &format!("\"{}", "#".repeat(hashes)))
+                } else {
+                    i + 1
+                }
+            }
+            b'"' => {
+                let mut j = i + 1;
+                while j < bytes.len() && bytes[j] != b'"' {
+                    j += if bytes[j] == b'\\' { 2 } else { 1 };
+                }
+                j + 1
+            }
+            b'\'' if next == Some(b'\\') => find(i + 2, "'"),
+            b'\'' => match text[i + 1..].chars().next() {
+                Some(c) if bytes.get(i + 1 + c.len_utf8()) == Some(&b'\'') => i + 2 + c.len_utf8(),
+                _ => i + 1,
+            },
+            b'{' => {
+                depth += 1;
+                i + 1
+            }
+            b'}' => {
+                depth = depth.saturating_sub(1);
+                i + 1
+            }
+            _ => i + 1,
+        }
+        .min(bytes.len());
+
+        // a brace belongs to the block it opens or closes
+        let inner = if bytes[start] == b'}' {
+            depth + 1
+        } else {
+            depth
+        };
+        for d in depths[start..i].iter_mut() {
+            *d = inner;
+        }
+    }
+
+    depths
+}